@@ -0,0 +1,235 @@
+use std::{
+    fs::File,
+    io::{Seek, SeekFrom, Write},
+    path::Path,
+};
+
+use crate::{
+    raw::{RawEntry, RawHeader, RawLongEntry},
+    Compression, Entry, EntryType, LumpName, Signature, Size,
+};
+
+#[derive(Debug)]
+pub enum WadEncodeError {
+    FailedToCreateFile(std::io::Error),
+    FailedToWrite(std::io::Error),
+    FailedToReadLump(crate::LumpReadError),
+    NoFilePath,
+    /// Longer than the 8 bytes a Doom directory entry holds.
+    NameTooLong(LumpName),
+    TooLarge,
+}
+
+#[derive(Debug, Clone)]
+pub struct Lump {
+    pub name: LumpName,
+    pub kind: EntryType,
+    pub compression: Compression,
+    /// Uncompressed size, only differs from `data.len()` for compressed
+    /// WAD2/WAD3 lumps.
+    pub real_size: Size,
+    pub data: Vec<u8>,
+}
+impl Lump {
+    pub fn new(name: LumpName, data: Vec<u8>) -> Lump {
+        Lump::typed(name, EntryType(0_u8), data)
+    }
+
+    pub fn typed(name: LumpName, kind: EntryType, data: Vec<u8>) -> Lump {
+        Lump {
+            name,
+            kind,
+            compression: Compression(0_u8),
+            real_size: Size(data.len() as i32),
+            data,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct WadBuilder {
+    pub signature: Signature,
+    pub lumps: Vec<Lump>,
+}
+impl WadBuilder {
+    pub fn new(signature: Signature) -> WadBuilder {
+        WadBuilder {
+            signature,
+            lumps: vec![],
+        }
+    }
+
+    pub fn iwad() -> WadBuilder {
+        WadBuilder::new(Signature::IWAD)
+    }
+
+    pub fn pwad() -> WadBuilder {
+        WadBuilder::new(Signature::PWAD)
+    }
+
+    pub fn lump(mut self, name: LumpName, data: Vec<u8>) -> WadBuilder {
+        self.push_lump(name, data);
+        self
+    }
+
+    pub fn typed_lump(mut self, name: LumpName, kind: EntryType, data: Vec<u8>) -> WadBuilder {
+        self.lumps.push(Lump::typed(name, kind, data));
+        self
+    }
+
+    pub fn push_lump(&mut self, name: LumpName, data: Vec<u8>) {
+        self.lumps.push(Lump::new(name, data));
+    }
+
+    /// Writes the header, then every lump's data in order, then the directory.
+    /// Offsets are relative to the writer's position when called, and the
+    /// written directory is returned.
+    pub fn write_to<W>(&self, writer: &mut W) -> Result<Vec<Entry>, WadEncodeError>
+    where
+        W: Write + Seek,
+    {
+        let base = writer
+            .stream_position()
+            .map_err(WadEncodeError::FailedToWrite)?;
+        writer
+            .write_all(&[0; RawHeader::SIZE])
+            .map_err(WadEncodeError::FailedToWrite)?;
+        let mut offset: usize = RawHeader::SIZE;
+        let mut entries = Vec::with_capacity(self.lumps.len());
+        for lump in &self.lumps {
+            let (start, size) = if lump.data.is_empty() {
+                (0, 0)
+            } else {
+                (to_i32(offset)?, to_i32(lump.data.len())?)
+            };
+            writer
+                .write_all(&lump.data)
+                .map_err(WadEncodeError::FailedToWrite)?;
+            offset += lump.data.len();
+            let mut entry = Entry::new(lump.name, start, size);
+            entry.real_size = lump.real_size;
+            entry.kind = lump.kind;
+            entry.compression = lump.compression;
+            entries.push(entry);
+        }
+        let dir_loc = to_i32(offset)?;
+        writer
+            .write_all(&encode_directory(self.signature, &entries)?)
+            .map_err(WadEncodeError::FailedToWrite)?;
+        let end = writer
+            .stream_position()
+            .map_err(WadEncodeError::FailedToWrite)?;
+        let header = encode_header(self.signature, to_i32(entries.len())?, dir_loc);
+        writer
+            .seek(SeekFrom::Start(base))
+            .and_then(|_| writer.write_all(&header))
+            .and_then(|_| writer.seek(SeekFrom::Start(end)))
+            .map_err(WadEncodeError::FailedToWrite)?;
+        Ok(entries)
+    }
+
+    pub fn save<P>(&self, path: P) -> Result<(), WadEncodeError>
+    where
+        P: AsRef<Path>,
+    {
+        let mut fout = File::create(path).map_err(WadEncodeError::FailedToCreateFile)?;
+        self.write_to(&mut fout)?;
+        fout.flush().map_err(WadEncodeError::FailedToWrite)?;
+        Ok(())
+    }
+}
+
+pub(crate) fn encode_header(signature: Signature, count: i32, dir_loc: i32) -> Vec<u8> {
+    let mut header = Vec::with_capacity(RawHeader::SIZE);
+    RawHeader {
+        signature: signature.0,
+        count,
+        dir_loc,
+    }
+    .encode(&mut header);
+    header
+}
+
+pub(crate) fn encode_directory(
+    signature: Signature,
+    entries: &[Entry],
+) -> Result<Vec<u8>, WadEncodeError> {
+    let mut directory = Vec::with_capacity(entries.len() * signature.entry_size());
+    for entry in entries {
+        if signature.has_long_entries() {
+            RawLongEntry::from(entry).encode(&mut directory);
+        } else {
+            RawEntry::try_from(entry)?.encode(&mut directory);
+        }
+    }
+    Ok(directory)
+}
+
+pub(crate) fn to_i32(value: usize) -> Result<i32, WadEncodeError> {
+    i32::try_from(value).map_err(|_| WadEncodeError::TooLarge)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::name;
+    use crate::Wad;
+
+    #[test]
+    fn test_round_trip_through_file() {
+        let builder = WadBuilder::pwad()
+            .lump(name("MAP01"), vec![])
+            .lump(name("THINGS"), vec![1, 2, 3, 4, 5, 6, 7, 8, 9, 10])
+            .lump(name("PLAYPAL"), vec![0xAB; 768]);
+        let path = std::env::temp_dir().join("wad_rs_test_builder_round_trip.wad");
+        builder.save(&path).unwrap();
+        let wad = Wad::from_file_path(&path).unwrap();
+        assert_eq!(wad.signature, Signature::PWAD);
+        let names: Vec<String> = wad.directory.iter().map(|e| e.name.to_string()).collect();
+        assert_eq!(names, ["MAP01", "THINGS", "PLAYPAL"]);
+        for (i, lump) in builder.lumps.iter().enumerate() {
+            assert_eq!(wad.read_lump(i).unwrap(), lump.data);
+        }
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_write_at_offset_is_relative() {
+        let mut cursor = std::io::Cursor::new(b"junk".to_vec());
+        cursor.seek(SeekFrom::End(0)).unwrap();
+        WadBuilder::iwad()
+            .lump(name("ENDOOM"), vec![7; 4000])
+            .write_to(&mut cursor)
+            .unwrap();
+        let wad = Wad::from_bytes(&cursor.get_ref()[4..]).unwrap();
+        assert_eq!(wad.signature, Signature::IWAD);
+        assert_eq!(wad.read_lump(0).unwrap(), vec![7; 4000]);
+    }
+
+    #[test]
+    fn test_wad3_round_trip() {
+        let long = LumpName::from_long_string("{blue_window01".into()).unwrap();
+        let mut cursor = std::io::Cursor::new(vec![]);
+        WadBuilder::new(Signature::WAD3)
+            .typed_lump(long, EntryType(0x43), vec![1, 2, 3])
+            .write_to(&mut cursor)
+            .unwrap();
+        assert_eq!(cursor.get_ref().len(), 12 + 3 + 32);
+        let wad = Wad::from_bytes(cursor.get_ref()).unwrap();
+        let entry = &wad.directory[0];
+        assert_eq!(entry.name.to_string(), "{blue_window01");
+        assert_eq!(entry.kind.0, 0x43);
+        assert_eq!(entry.real_size.0, 3);
+        assert_eq!(wad.read_lump(0).unwrap(), [1, 2, 3]);
+    }
+
+    #[test]
+    fn test_long_name_in_doom_directory() {
+        let long = LumpName::from_long_string("{blue_window01".into()).unwrap();
+        let mut cursor = std::io::Cursor::new(vec![]);
+        let result = WadBuilder::pwad()
+            .lump(long, vec![1, 2, 3])
+            .write_to(&mut cursor);
+        assert!(matches!(result, Err(WadEncodeError::NameTooLong(n)) if n == long));
+    }
+}
@@ -0,0 +1,269 @@
+use std::{
+    cell::RefCell,
+    fs::{File, OpenOptions},
+    io::{Seek, SeekFrom, Write},
+    path::Path,
+};
+
+use crate::{
+    builder::{encode_directory, encode_header, to_i32},
+    validate, Entry, Lump, LumpName, Size, Wad, WadBuilder, WadDecodeError, WadEncodeError,
+};
+
+impl Wad {
+    /// Inserts a new lump at `index`, shifting later entries.
+    ///
+    /// Panics if `index > directory.len()`, like `Vec::insert`.
+    pub fn insert_lump(&mut self, index: usize, name: LumpName, data: Vec<u8>) {
+        self.directory.insert(index, Entry::staged(name, data));
+        self.reindex();
+    }
+
+    pub fn push_lump(&mut self, name: LumpName, data: Vec<u8>) {
+        let index = self.directory.len();
+        self.insert_lump(index, name, data);
+    }
+
+    /// Panics if `index` is out of bounds.
+    pub fn remove_lump(&mut self, index: usize) -> Entry {
+        let entry = self.directory.remove(index);
+        self.reindex();
+        entry
+    }
+
+    /// Panics if `index` is out of bounds.
+    pub fn rename_lump(&mut self, index: usize, name: LumpName) {
+        self.directory[index].name = name;
+        self.reindex();
+    }
+
+    /// Moves the entry at `from` so that it ends up at position `to`.
+    ///
+    /// Panics if either index is out of bounds.
+    pub fn move_lump(&mut self, from: usize, to: usize) {
+        let entry = self.directory.remove(from);
+        self.directory.insert(to, entry);
+        self.reindex();
+    }
+
+    /// Panics if `index` is out of bounds.
+    pub fn replace_lump(&mut self, index: usize, data: Vec<u8>) {
+        let entry = &mut self.directory[index];
+        entry.size = Size::of(&data);
+        entry.real_size = entry.size;
+        entry.staged = Some(data);
+    }
+
+    fn to_builder(&self) -> Result<WadBuilder, WadEncodeError> {
+        let mut builder = WadBuilder::new(self.signature);
+        for entry in &self.directory {
+            let data = self
+                .lump_data(entry)
+                .map_err(WadEncodeError::FailedToReadLump)?;
+            builder.lumps.push(Lump {
+                name: entry.name,
+                kind: entry.kind,
+                compression: entry.compression,
+                real_size: if entry.is_modified() {
+                    Size(data.len() as i32)
+                } else {
+                    entry.real_size
+                },
+                data,
+            });
+        }
+        Ok(builder)
+    }
+
+    /// Writes a freshly laid out copy of this WAD, including pending edits.
+    pub fn save_to<W>(&self, writer: &mut W) -> Result<(), WadEncodeError>
+    where
+        W: Write + Seek,
+    {
+        self.to_builder()?.write_to(writer)?;
+        Ok(())
+    }
+
+    /// Rewrites the file this WAD was opened from with all pending edits.
+    pub fn save(&mut self) -> Result<(), WadEncodeError> {
+        let path = self.path.clone().ok_or(WadEncodeError::NoFilePath)?;
+        self.save_as(path)
+    }
+
+    /// Writes this WAD to `path` and keeps working on the new file. The WAD
+    /// is written to a temporary file next to `path` that only replaces it
+    /// once complete, so a failed save leaves the old file intact.
+    pub fn save_as<P>(&mut self, path: P) -> Result<(), WadEncodeError>
+    where
+        P: AsRef<Path>,
+    {
+        let path = path.as_ref();
+        let builder = self.to_builder()?;
+        let mut temp_name = path.file_name().unwrap_or_default().to_os_string();
+        temp_name.push(".tmp");
+        let temp = path.with_file_name(temp_name);
+        let entries = match write_and_rename(&builder, &temp, path) {
+            Ok(entries) => entries,
+            Err(e) => {
+                let _ = std::fs::remove_file(&temp);
+                return Err(e);
+            }
+        };
+        let file = File::open(path).map_err(WadEncodeError::FailedToCreateFile)?;
+        self.reopen(path, file, entries);
+        Ok(())
+    }
+
+    /// Saves the way classic editors do: new and replaced lump data plus a new
+    /// directory are appended to the end of the file, and the header is
+    /// updated to point at them. Untouched lumps are never rewritten, so
+    /// replaced data is left behind as dead space.
+    pub fn save_appending(&mut self) -> Result<(), WadEncodeError> {
+        let path = self.path.clone().ok_or(WadEncodeError::NoFilePath)?;
+        let mut fout = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(&path)
+            .map_err(WadEncodeError::FailedToCreateFile)?;
+        let mut offset = fout
+            .seek(SeekFrom::End(0))
+            .map_err(WadEncodeError::FailedToWrite)? as usize;
+        let mut entries = self.directory.clone();
+        for entry in &mut entries {
+            if let Some(data) = entry.staged.take() {
+                if data.is_empty() {
+                    entry.start.0 = 0;
+                } else {
+                    entry.start.0 = to_i32(offset)?;
+                    fout.write_all(&data)
+                        .map_err(WadEncodeError::FailedToWrite)?;
+                    offset += data.len();
+                }
+                entry.size.0 = to_i32(data.len())?;
+                entry.real_size = entry.size;
+            }
+        }
+        fout.write_all(&encode_directory(self.signature, &entries)?)
+            .map_err(WadEncodeError::FailedToWrite)?;
+        let header = encode_header(self.signature, to_i32(entries.len())?, to_i32(offset)?);
+        fout.seek(SeekFrom::Start(0))
+            .and_then(|_| fout.write_all(&header))
+            .and_then(|_| fout.flush())
+            .map_err(WadEncodeError::FailedToWrite)?;
+        self.reopen(&path, fout, entries);
+        Ok(())
+    }
+
+    fn reopen(&mut self, path: &Path, file: File, entries: Vec<Entry>) {
+        let file_len = file.metadata().map(|m| m.len()).unwrap_or(u64::MAX);
+        self.warnings
+            .retain(|w| matches!(w, WadDecodeError::InvalidSignature(_)));
+        self.warnings
+            .extend(validate::check_entries(&entries, file_len));
+        self.directory = entries;
+        self.reindex();
+        self.reader = RefCell::new(Box::new(file));
+        self.path = Some(path.to_path_buf());
+    }
+}
+
+fn write_and_rename(
+    builder: &WadBuilder,
+    temp: &Path,
+    path: &Path,
+) -> Result<Vec<Entry>, WadEncodeError> {
+    let mut fout = File::create(temp).map_err(WadEncodeError::FailedToCreateFile)?;
+    let entries = builder.write_to(&mut fout)?;
+    fout.flush()
+        .and_then(|_| fout.sync_all())
+        .map_err(WadEncodeError::FailedToWrite)?;
+    std::fs::rename(temp, path).map_err(WadEncodeError::FailedToWrite)?;
+    Ok(entries)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::{decode, name};
+
+    fn names(wad: &Wad) -> Vec<String> {
+        wad.directory.iter().map(|e| e.name.to_string()).collect()
+    }
+
+    fn sample(path: &Path) -> Wad {
+        WadBuilder::pwad()
+            .lump(name("A"), b"aaaa".to_vec())
+            .lump(name("B"), b"bb".to_vec())
+            .lump(name("C"), b"c".to_vec())
+            .save(path)
+            .unwrap();
+        Wad::from_file_path(path).unwrap()
+    }
+
+    #[test]
+    fn test_edit_and_save() {
+        let path = std::env::temp_dir().join("wad_rs_test_edit_and_save.wad");
+        let mut wad = sample(&path);
+        wad.insert_lump(1, name("NEW"), b"new".to_vec());
+        wad.remove_lump(2);
+        wad.rename_lump(0, name("AA"));
+        wad.move_lump(2, 0);
+        wad.replace_lump(1, b"replaced".to_vec());
+        assert_eq!(names(&wad), ["C", "AA", "NEW"]);
+        let sizes: Vec<i32> = wad.directory.iter().map(|e| e.size.0).collect();
+        assert_eq!(sizes, [1, 8, 3]);
+        wad.save().unwrap();
+        assert!(!path
+            .with_file_name("wad_rs_test_edit_and_save.wad.tmp")
+            .exists());
+        assert!(wad.directory.iter().all(|e| !e.is_modified()));
+        let reloaded = Wad::from_file_path(&path).unwrap();
+        assert_eq!(names(&reloaded), ["C", "AA", "NEW"]);
+        assert_eq!(reloaded.read_lump(0).unwrap(), b"c");
+        assert_eq!(reloaded.read_lump(1).unwrap(), b"replaced");
+        assert_eq!(wad.read_lump(2).unwrap(), b"new");
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_save_appending_keeps_old_data() {
+        let path = std::env::temp_dir().join("wad_rs_test_save_appending.wad");
+        let mut wad = sample(&path);
+        let untouched_start = wad.directory[0].start.0;
+        wad.replace_lump(1, b"longer".to_vec());
+        wad.push_lump(name("D"), b"dd".to_vec());
+        wad.save_appending().unwrap();
+        let reloaded = Wad::from_file_path(&path).unwrap();
+        assert_eq!(names(&reloaded), ["A", "B", "C", "D"]);
+        assert_eq!(reloaded.directory[0].start.0, untouched_start);
+        assert_eq!(reloaded.read_lump(1).unwrap(), b"longer");
+        assert_eq!(reloaded.read_lump(3).unwrap(), b"dd");
+        assert_eq!(wad.read_lump(3).unwrap(), b"dd");
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_save_requires_path() {
+        let mut wad = decode(&WadBuilder::pwad());
+        assert!(matches!(wad.save(), Err(WadEncodeError::NoFilePath)));
+    }
+
+    #[test]
+    fn test_failed_save_keeps_original() {
+        let path = std::env::temp_dir().join("wad_rs_test_failed_save.wad");
+        let mut wad = sample(&path);
+        let before = std::fs::read(&path).unwrap();
+        // A directory in the way of the temporary file makes the save fail
+        // before the original is touched.
+        let temp = path.with_file_name("wad_rs_test_failed_save.wad.tmp");
+        std::fs::create_dir_all(&temp).unwrap();
+        wad.replace_lump(0, b"changed".to_vec());
+        assert!(matches!(
+            wad.save(),
+            Err(WadEncodeError::FailedToCreateFile(_))
+        ));
+        assert_eq!(std::fs::read(&path).unwrap(), before);
+        std::fs::remove_dir(temp).unwrap();
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,102 @@
+//! Flats: headerless, fully opaque bitmaps stored row by row.
+
+use crate::{Namespace, Wad};
+
+use super::{GraphicDecodeError, GraphicEncodeError, IndexedImage};
+
+/// Dimensions of a flat lump of `len` bytes: any square, or the 64x128
+/// scrolling flats of Heretic and Hexen.
+pub fn flat_size(len: usize) -> Option<(usize, usize)> {
+    if len == 64 * 128 {
+        return Some((64, 128));
+    }
+    let side = len.isqrt();
+    (side > 0 && side * side == len).then_some((side, side))
+}
+
+impl IndexedImage {
+    pub fn decode_flat(bytes: &[u8]) -> Option<IndexedImage> {
+        let (width, height) = flat_size(bytes.len())?;
+        Some(IndexedImage {
+            width,
+            height,
+            pixels: bytes.iter().copied().map(Some).collect(),
+        })
+    }
+
+    pub fn encode_flat(&self) -> Result<Vec<u8>, GraphicEncodeError> {
+        let (width, height) = (self.width, self.height);
+        if flat_size(width * height) != Some((width, height)) {
+            return Err(GraphicEncodeError::UnsupportedSize { width, height });
+        }
+        self.pixels
+            .iter()
+            .map(|&pixel| pixel.ok_or(GraphicEncodeError::TransparentPixels))
+            .collect()
+    }
+}
+
+impl Wad {
+    /// Looks the flat up between the `F_START` and `F_END` markers only.
+    pub fn flat(&self, name: &str) -> Result<IndexedImage, GraphicDecodeError> {
+        let index = self
+            .find_in_namespace_index(name, Namespace::Flats)
+            .ok_or_else(|| GraphicDecodeError::NoSuchLump(name.into()))?;
+        let data = self
+            .read_lump(index)
+            .map_err(GraphicDecodeError::FailedToReadLump)?;
+        IndexedImage::decode_flat(&data).ok_or_else(|| GraphicDecodeError::InvalidLump(name.into()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::decode;
+    use crate::{LumpName, WadBuilder};
+
+    #[test]
+    fn test_sizes() {
+        assert_eq!(flat_size(4096), Some((64, 64)));
+        assert_eq!(flat_size(8192), Some((64, 128)));
+        assert_eq!(flat_size(128 * 128), Some((128, 128)));
+        assert_eq!(flat_size(4000), None);
+        assert_eq!(flat_size(0), None);
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let bytes: Vec<u8> = (0..4096).map(|i| (i % 251) as u8).collect();
+        let image = IndexedImage::decode_flat(&bytes).unwrap();
+        assert_eq!((image.width, image.height), (64, 64));
+        assert_eq!(image.get(3, 1), Some(67));
+        assert_eq!(image.encode_flat().unwrap(), bytes);
+
+        let mut holey = image.clone();
+        holey.set(0, 0, None);
+        assert!(matches!(
+            holey.encode_flat(),
+            Err(GraphicEncodeError::TransparentPixels)
+        ));
+        assert!(matches!(
+            IndexedImage::new(64, 32).encode_flat(),
+            Err(GraphicEncodeError::UnsupportedSize { .. })
+        ));
+    }
+
+    #[test]
+    fn test_from_wad() {
+        let wad = decode(
+            &WadBuilder::pwad()
+                .lump(LumpName::known("FLOOR0_1"), vec![1; 10])
+                .lump(LumpName::known("F_START"), vec![])
+                .lump(LumpName::known("FLOOR0_1"), vec![2; 4096])
+                .lump(LumpName::known("F_END"), vec![]),
+        );
+        assert_eq!(wad.flat("floor0_1").unwrap().get(63, 63), Some(2));
+        assert!(matches!(
+            wad.flat("FLOOR0_2"),
+            Err(GraphicDecodeError::NoSuchLump(_))
+        ));
+    }
+}
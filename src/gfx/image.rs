@@ -0,0 +1,30 @@
+/// A palette-indexed bitmap; `None` pixels are transparent.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IndexedImage {
+    pub width: usize,
+    pub height: usize,
+    /// Row by row from the top left.
+    pub pixels: Vec<Option<u8>>,
+}
+impl IndexedImage {
+    /// A fully transparent image.
+    pub fn new(width: usize, height: usize) -> IndexedImage {
+        IndexedImage {
+            width,
+            height,
+            pixels: vec![None; width * height],
+        }
+    }
+
+    pub fn get(&self, x: usize, y: usize) -> Option<u8> {
+        self.pixels[y * self.width + x]
+    }
+
+    pub fn set(&mut self, x: usize, y: usize, pixel: Option<u8>) {
+        self.pixels[y * self.width + x] = pixel;
+    }
+
+    pub fn is_opaque(&self) -> bool {
+        self.pixels.iter().all(Option::is_some)
+    }
+}
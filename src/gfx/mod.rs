@@ -0,0 +1,57 @@
+//! Graphics lumps: palettes, colormaps and images.
+
+mod flat;
+mod image;
+mod palette;
+mod picture;
+mod png;
+mod texture;
+
+pub use flat::flat_size;
+pub use image::IndexedImage;
+pub use palette::{Colormap, Palette, Playpal, Rgb, COLORMAP_TABLES, PLAYPAL_PALETTES};
+pub use picture::Picture;
+pub use png::PngDecodeError;
+pub use texture::{Pnames, TextureDef, TextureLayout, TextureList, TexturePatch};
+
+use crate::{LumpReadError, Wad};
+
+#[derive(Debug)]
+pub enum GraphicDecodeError {
+    NoSuchLump(String),
+    FailedToReadLump(LumpReadError),
+    /// The lump's contents do not match the expected format.
+    InvalidLump(String),
+}
+
+#[derive(Debug)]
+pub enum GraphicEncodeError {
+    /// Dimensions the format cannot store.
+    TooLarge {
+        width: usize,
+        height: usize,
+    },
+    UnsupportedSize {
+        width: usize,
+        height: usize,
+    },
+    /// The format has no transparency.
+    TransparentPixels,
+}
+
+impl Wad {
+    /// Reads and decodes the last lump named `name` with `decode`.
+    pub(crate) fn decode_lump<T>(
+        &self,
+        name: &str,
+        decode: impl FnOnce(&[u8]) -> Option<T>,
+    ) -> Result<T, GraphicDecodeError> {
+        let index = self
+            .find_lump_index(name)
+            .ok_or_else(|| GraphicDecodeError::NoSuchLump(name.into()))?;
+        let data = self
+            .read_lump(index)
+            .map_err(GraphicDecodeError::FailedToReadLump)?;
+        decode(&data).ok_or_else(|| GraphicDecodeError::InvalidLump(name.into()))
+    }
+}
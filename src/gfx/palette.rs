@@ -0,0 +1,214 @@
+use crate::Wad;
+
+use super::GraphicDecodeError;
+
+/// Palettes in a vanilla PLAYPAL: normal, eight pain reds, four pickup
+/// golds and the radiation suit green.
+pub const PLAYPAL_PALETTES: usize = 14;
+/// Tables in a vanilla COLORMAP: 32 light levels, invulnerability and an
+/// all-black table.
+pub const COLORMAP_TABLES: usize = 34;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct Rgb {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+}
+impl Rgb {
+    fn distance(self, other: Rgb) -> u32 {
+        let d = |a: u8, b: u8| (a as i32 - b as i32).pow(2) as u32;
+        d(self.r, other.r) + d(self.g, other.g) + d(self.b, other.b)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Palette(pub [Rgb; 256]);
+impl Palette {
+    pub const SIZE: usize = 768;
+
+    pub fn decode(bytes: &[u8]) -> Option<Palette> {
+        if bytes.len() < Palette::SIZE {
+            return None;
+        }
+        let mut colors = [Rgb::default(); 256];
+        for (color, rgb) in colors.iter_mut().zip(bytes.chunks_exact(3)) {
+            *color = Rgb {
+                r: rgb[0],
+                g: rgb[1],
+                b: rgb[2],
+            };
+        }
+        Some(Palette(colors))
+    }
+
+    pub fn encode(&self, out: &mut Vec<u8>) {
+        for color in &self.0 {
+            out.extend([color.r, color.g, color.b]);
+        }
+    }
+
+    /// The index of the closest color; ties go to the lowest index.
+    pub fn nearest(&self, color: Rgb) -> u8 {
+        let mut best = 0;
+        for (index, candidate) in self.0.iter().enumerate() {
+            if candidate.distance(color) < self.0[best].distance(color) {
+                best = index;
+            }
+        }
+        best as u8
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Playpal {
+    pub palettes: Vec<Palette>,
+}
+impl Playpal {
+    /// Accepts any whole number of palettes; trailing bytes are an error.
+    pub fn decode(bytes: &[u8]) -> Option<Playpal> {
+        if bytes.is_empty() || !bytes.len().is_multiple_of(Palette::SIZE) {
+            return None;
+        }
+        Some(Playpal {
+            palettes: bytes
+                .chunks_exact(Palette::SIZE)
+                .map(Palette::decode)
+                .collect::<Option<_>>()?,
+        })
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(self.palettes.len() * Palette::SIZE);
+        for palette in &self.palettes {
+            palette.encode(&mut out);
+        }
+        out
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Colormap {
+    pub tables: Vec<[u8; 256]>,
+}
+impl Colormap {
+    /// Accepts any whole number of tables. Some IWADs pad the lump, so a
+    /// partial trailing table is ignored.
+    pub fn decode(bytes: &[u8]) -> Option<Colormap> {
+        if bytes.len() < 256 {
+            return None;
+        }
+        Some(Colormap {
+            tables: bytes
+                .chunks_exact(256)
+                .map(|table| table.try_into().unwrap())
+                .collect(),
+        })
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        self.tables.concat()
+    }
+
+    /// Rebuilds the vanilla tables: 32 levels fading linearly to black, an
+    /// inverted grayscale for invulnerability and a black table.
+    pub fn generate(palette: &Palette) -> Colormap {
+        let mut tables = Vec::with_capacity(COLORMAP_TABLES);
+        for level in 0..32u32 {
+            let scale = |c: u8| ((c as u32 * (32 - level) + 16) / 32) as u8;
+            tables.push(palette.0.map(|c| {
+                palette.nearest(Rgb {
+                    r: scale(c.r),
+                    g: scale(c.g),
+                    b: scale(c.b),
+                })
+            }));
+        }
+        tables.push(palette.0.map(|c| {
+            let gray = (c.r as u32 * 299 + c.g as u32 * 587 + c.b as u32 * 114) / 1000;
+            let inverted = 255 - gray as u8;
+            palette.nearest(Rgb {
+                r: inverted,
+                g: inverted,
+                b: inverted,
+            })
+        }));
+        tables.push([palette.nearest(Rgb::default()); 256]);
+        Colormap { tables }
+    }
+}
+
+impl Wad {
+    pub fn playpal(&self) -> Result<Playpal, GraphicDecodeError> {
+        self.decode_lump("PLAYPAL", Playpal::decode)
+    }
+
+    pub fn colormap(&self) -> Result<Colormap, GraphicDecodeError> {
+        self.decode_lump("COLORMAP", Colormap::decode)
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::testutil::decode;
+    use crate::{LumpName, WadBuilder};
+
+    /// A palette with a gray ramp in the first half and pure reds in the
+    /// second.
+    pub(crate) fn test_palette() -> Palette {
+        let mut colors = [Rgb::default(); 256];
+        for i in 0..128 {
+            let v = (i * 2) as u8;
+            colors[i] = Rgb { r: v, g: v, b: v };
+            colors[128 + i] = Rgb { r: v, g: 0, b: 0 };
+        }
+        colors[127] = Rgb {
+            r: 255,
+            g: 255,
+            b: 255,
+        };
+        Palette(colors)
+    }
+
+    #[test]
+    fn test_playpal_round_trip() {
+        let playpal = Playpal {
+            palettes: vec![test_palette(); PLAYPAL_PALETTES],
+        };
+        let bytes = playpal.encode();
+        assert_eq!(bytes.len(), 14 * 768);
+        assert_eq!(Playpal::decode(&bytes), Some(playpal));
+        assert_eq!(Playpal::decode(&bytes[1..]), None);
+        assert_eq!(Playpal::decode(&[]), None);
+    }
+
+    #[test]
+    fn test_generate_colormap() {
+        let palette = test_palette();
+        let colormap = Colormap::generate(&palette);
+        assert_eq!(colormap.tables.len(), COLORMAP_TABLES);
+        // Full brightness maps every color to itself, except the duplicate
+        // black at index 128.
+        assert!((0..256).all(|i| colormap.tables[0][i] as usize == i || i == 128));
+        assert_eq!(colormap.tables[16][127], 64);
+        assert_eq!(colormap.tables[16][255], 128 + 63);
+        assert_eq!(colormap.tables[32][0], 127);
+        assert_eq!(colormap.tables[33], [0; 256]);
+    }
+
+    #[test]
+    fn test_from_wad() {
+        let colormap = Colormap::generate(&test_palette());
+        let wad = decode(
+            &WadBuilder::iwad()
+                .lump(LumpName::known("PLAYPAL"), vec![0; 700])
+                .lump(LumpName::known("COLORMAP"), colormap.encode()),
+        );
+        assert_eq!(wad.colormap().unwrap(), colormap);
+        assert!(matches!(
+            wad.playpal(),
+            Err(GraphicDecodeError::InvalidLump(name)) if name == "PLAYPAL"
+        ));
+    }
+}
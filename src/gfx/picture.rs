@@ -0,0 +1,223 @@
+//! The column-post "picture" format of patches, sprites and menu graphics.
+
+use crate::raw::{i16_at, i32_at, u16_at};
+use crate::Wad;
+
+use super::{GraphicDecodeError, GraphicEncodeError, IndexedImage};
+
+/// Longest run written as one post.
+const MAX_POST: usize = 254;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Picture {
+    /// How far left of the drawing position the image starts.
+    pub left: i16,
+    /// How far above the drawing position the image starts.
+    pub top: i16,
+    pub image: IndexedImage,
+}
+impl Picture {
+    /// Understands tall patches: a post whose top delta is not past the
+    /// previous post's top continues from it instead of from row 0.
+    ///
+    /// The whole lump is checked before the image is allocated, so a short
+    /// lump can't claim a huge size.
+    pub fn decode(bytes: &[u8]) -> Option<Picture> {
+        if bytes.len() < 8 {
+            return None;
+        }
+        let width = u16_at(bytes, 0) as usize;
+        let height = u16_at(bytes, 2) as usize;
+        let columns_end = 8 + width * 4;
+        if bytes.len() < columns_end {
+            return None;
+        }
+        let mut posts = vec![];
+        for x in 0..width {
+            let mut at = usize::try_from(i32_at(bytes, 8 + x * 4) as u32).ok()?;
+            if !(columns_end..bytes.len()).contains(&at) {
+                return None;
+            }
+            let mut top: isize = -1;
+            loop {
+                let delta = *bytes.get(at)?;
+                if delta == 0xFF {
+                    break;
+                }
+                let length = *bytes.get(at + 1)? as usize;
+                top = match delta as isize {
+                    delta if delta <= top => top + delta,
+                    delta => delta,
+                };
+                // Skip the padding bytes around the post's pixels.
+                posts.push((x, top as usize, bytes.get(at + 3..at + 3 + length)?));
+                at += length + 4;
+            }
+        }
+        let mut image = IndexedImage::new(width, height);
+        for (x, top, pixels) in posts {
+            for (y, &pixel) in (top..height).zip(pixels) {
+                image.set(x, y, Some(pixel));
+            }
+        }
+        Some(Picture {
+            left: i16_at(bytes, 4),
+            top: i16_at(bytes, 6),
+            image,
+        })
+    }
+
+    /// Posts past row 254 are placed with tall-patch relative deltas,
+    /// inserting empty posts where a delta would not fit in a byte.
+    pub fn encode(&self) -> Result<Vec<u8>, GraphicEncodeError> {
+        let (width, height) = (self.image.width, self.image.height);
+        let (Ok(w), Ok(h)) = (u16::try_from(width), u16::try_from(height)) else {
+            return Err(GraphicEncodeError::TooLarge { width, height });
+        };
+        let mut out = vec![];
+        out.extend(w.to_le_bytes());
+        out.extend(h.to_le_bytes());
+        out.extend(self.left.to_le_bytes());
+        out.extend(self.top.to_le_bytes());
+        let offsets = out.len();
+        out.resize(offsets + width * 4, 0);
+        for x in 0..width {
+            let start = out.len() as u32;
+            out[offsets + x * 4..offsets + x * 4 + 4].copy_from_slice(&start.to_le_bytes());
+            let mut last: isize = -1;
+            let mut y = 0;
+            while y < height {
+                if self.image.get(x, y).is_none() {
+                    y += 1;
+                    continue;
+                }
+                let mut end = y;
+                while end < height && end - y < MAX_POST && self.image.get(x, end).is_some() {
+                    end += 1;
+                }
+                let delta = loop {
+                    if let Some(delta) = top_delta(y as isize, last) {
+                        break delta;
+                    }
+                    let step = if last < 254 {
+                        254
+                    } else {
+                        last + last.min(254)
+                    };
+                    out.extend([top_delta(step, last).unwrap(), 0, 0, 0]);
+                    last = step;
+                };
+                out.extend([delta, (end - y) as u8, 0]);
+                out.extend((y..end).map(|y| self.image.get(x, y).unwrap()));
+                out.push(0);
+                last = y as isize;
+                y = end;
+            }
+            out.push(0xFF);
+        }
+        Ok(out)
+    }
+}
+
+/// The byte that places a post at row `top` after one at row `last`, if
+/// there is one.
+fn top_delta(top: isize, last: isize) -> Option<u8> {
+    if top > last && top < 255 {
+        Some(top as u8)
+    } else if last >= 0 && top - last <= last.min(254) {
+        Some((top - last) as u8)
+    } else {
+        None
+    }
+}
+
+impl Wad {
+    pub fn picture(&self, name: &str) -> Result<Picture, GraphicDecodeError> {
+        self.decode_lump(name, Picture::decode)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn checkerboard(width: usize, height: usize) -> IndexedImage {
+        let mut image = IndexedImage::new(width, height);
+        for y in 0..height {
+            for x in 0..width {
+                if (x + y / 3) % 2 == 0 {
+                    image.set(x, y, Some((x * 7 + y) as u8));
+                }
+            }
+        }
+        image
+    }
+
+    #[test]
+    fn test_decode() {
+        // A 2x3 picture: column 0 has one post at row 1, column 1 is empty.
+        let mut bytes = vec![2, 0, 3, 0, 1, 0, 0xFE, 0xFF];
+        bytes.extend(16u32.to_le_bytes());
+        bytes.extend(23u32.to_le_bytes());
+        bytes.extend([1, 2, 0, 10, 11, 0, 0xFF]);
+        bytes.push(0xFF);
+        let picture = Picture::decode(&bytes).unwrap();
+        assert_eq!((picture.left, picture.top), (1, -2));
+        assert_eq!(
+            picture.image.pixels,
+            [None, None, Some(10), None, Some(11), None]
+        );
+        assert_eq!(picture.encode().unwrap(), bytes);
+        assert_eq!(Picture::decode(&bytes[..20]), None);
+    }
+
+    #[test]
+    fn test_decode_rejects_bad_header() {
+        // Claims 65535x65535 with no room for the column offsets.
+        assert_eq!(
+            Picture::decode(&[0xFF, 0xFF, 0xFF, 0xFF, 0, 0, 0, 0, 0, 0]),
+            None
+        );
+        // A column offset pointing back into the header.
+        let mut bytes = vec![1, 0, 1, 0, 0, 0, 0, 0];
+        bytes.extend(4u32.to_le_bytes());
+        bytes.push(0xFF);
+        assert_eq!(Picture::decode(&bytes), None);
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let picture = Picture {
+            left: -5,
+            top: 30,
+            image: checkerboard(13, 40),
+        };
+        let bytes = picture.encode().unwrap();
+        assert_eq!(Picture::decode(&bytes), Some(picture));
+    }
+
+    #[test]
+    fn test_tall_patch() {
+        let mut image = checkerboard(3, 700);
+        // Long opaque runs across the 254-row boundary.
+        for y in 200..600 {
+            image.set(1, y, Some(9));
+        }
+        let picture = Picture {
+            left: 0,
+            top: 0,
+            image,
+        };
+        let bytes = picture.encode().unwrap();
+        assert_eq!(Picture::decode(&bytes), Some(picture));
+
+        let mut column = IndexedImage::new(1, 1000);
+        column.set(0, 999, Some(1));
+        let picture = Picture {
+            left: 0,
+            top: 0,
+            image: column,
+        };
+        assert_eq!(Picture::decode(&picture.encode().unwrap()), Some(picture));
+    }
+}
@@ -0,0 +1,532 @@
+//! PNG conversion of indexed images, colored through a PLAYPAL palette.
+
+use std::collections::HashMap;
+use std::io::{Read, Write};
+
+use flate2::{read::ZlibDecoder, write::ZlibEncoder, Compression};
+
+use super::{IndexedImage, Palette, Picture, Rgb};
+
+const SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1A, b'\n'];
+
+/// Alpha values below this are imported as transparent.
+const ALPHA_THRESHOLD: u8 = 128;
+
+/// Wider or taller than any picture or flat a WAD can hold.
+const MAX_DIMENSION: usize = i16::MAX as usize;
+
+#[derive(Debug)]
+pub enum PngDecodeError {
+    InvalidSignature,
+    Truncated,
+    BadChecksum([u8; 4]),
+    MissingChunk(&'static str),
+    /// Valid PNG this decoder does not handle, such as interlacing.
+    Unsupported(&'static str),
+    FailedToInflate(std::io::Error),
+    /// A `grAb` offset that does not fit a picture header.
+    OffsetOutOfRange(i32),
+    Malformed(&'static str),
+}
+
+fn be_u32(bytes: &[u8], at: usize) -> u32 {
+    u32::from_be_bytes(bytes[at..at + 4].try_into().unwrap())
+}
+
+fn write_chunk(out: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    out.extend((data.len() as u32).to_be_bytes());
+    let start = out.len();
+    out.extend(kind);
+    out.extend(data);
+    let crc = crc32fast::hash(&out[start..]);
+    out.extend(crc.to_be_bytes());
+}
+
+/// Writes an 8-bit paletted PNG with the palette as PLTE. Transparency
+/// takes an index the image does not use; when every index is used the
+/// image is written as RGBA instead.
+fn encode(image: &IndexedImage, palette: &Palette, offsets: Option<(i32, i32)>) -> Vec<u8> {
+    let mut used = [false; 256];
+    for pixel in image.pixels.iter().flatten() {
+        used[*pixel as usize] = true;
+    }
+    let transparent = match image.is_opaque() {
+        true => None,
+        false => Some(used.iter().position(|used| !used)),
+    };
+
+    let mut out = SIGNATURE.to_vec();
+    let mut header = vec![];
+    header.extend((image.width as u32).to_be_bytes());
+    header.extend((image.height as u32).to_be_bytes());
+    let truecolor = transparent == Some(None);
+    header.extend([8, if truecolor { 6 } else { 3 }, 0, 0, 0]);
+    write_chunk(&mut out, b"IHDR", &header);
+    if let Some((x, y)) = offsets {
+        write_chunk(
+            &mut out,
+            b"grAb",
+            &[x.to_be_bytes(), y.to_be_bytes()].concat(),
+        );
+    }
+    if !truecolor {
+        let mut plte = vec![];
+        palette.encode(&mut plte);
+        write_chunk(&mut out, b"PLTE", &plte);
+    }
+    if let Some(Some(index)) = transparent {
+        let mut alpha = vec![255; index + 1];
+        alpha[index] = 0;
+        write_chunk(&mut out, b"tRNS", &alpha);
+    }
+
+    let mut raw = vec![];
+    for row in image.pixels.chunks(image.width.max(1)) {
+        // Filter type 0: the row as is.
+        raw.push(0);
+        for &pixel in row {
+            match (pixel, transparent) {
+                (Some(index), _) if truecolor => {
+                    let c = palette.0[index as usize];
+                    raw.extend([c.r, c.g, c.b, 255]);
+                }
+                (None, _) if truecolor => raw.extend([0; 4]),
+                (Some(index), _) => raw.push(index),
+                (None, Some(Some(index))) => raw.push(index as u8),
+                (None, _) => unreachable!("transparent pixels in an opaque image"),
+            }
+        }
+    }
+    let mut encoder = ZlibEncoder::new(vec![], Compression::default());
+    encoder.write_all(&raw).unwrap();
+    write_chunk(&mut out, b"IDAT", &encoder.finish().unwrap());
+    write_chunk(&mut out, b"IEND", &[]);
+    out
+}
+
+fn paeth(a: u8, b: u8, c: u8) -> u8 {
+    let p = a as i16 + b as i16 - c as i16;
+    let (pa, pb, pc) = (
+        (p - a as i16).abs(),
+        (p - b as i16).abs(),
+        (p - c as i16).abs(),
+    );
+    if pa <= pb && pa <= pc {
+        a
+    } else if pb <= pc {
+        b
+    } else {
+        c
+    }
+}
+
+/// Reverses the per-row filters, dropping the filter bytes.
+fn unfilter(
+    data: &[u8],
+    stride: usize,
+    bpp: usize,
+    height: usize,
+) -> Result<Vec<u8>, PngDecodeError> {
+    let too_large = || PngDecodeError::Unsupported("image size");
+    let filtered = stride
+        .checked_add(1)
+        .and_then(|row| row.checked_mul(height))
+        .ok_or_else(too_large)?;
+    if data.len() < filtered {
+        return Err(PngDecodeError::Truncated);
+    }
+    let mut out = vec![0u8; stride.checked_mul(height).ok_or_else(too_large)?];
+    for y in 0..height {
+        let filter = data[y * (stride + 1)];
+        let row = &data[y * (stride + 1) + 1..(y + 1) * (stride + 1)];
+        let (done, rest) = out.split_at_mut(y * stride);
+        let previous = if y == 0 {
+            None
+        } else {
+            Some(&done[(y - 1) * stride..])
+        };
+        let current = &mut rest[..stride];
+        for x in 0..stride {
+            let a = if x >= bpp { current[x - bpp] } else { 0 };
+            let b = previous.map_or(0, |p| p[x]);
+            let c = match previous {
+                Some(p) if x >= bpp => p[x - bpp],
+                _ => 0,
+            };
+            current[x] = row[x].wrapping_add(match filter {
+                0 => 0,
+                1 => a,
+                2 => b,
+                3 => ((a as u16 + b as u16) / 2) as u8,
+                4 => paeth(a, b, c),
+                _ => return Err(PngDecodeError::Malformed("unknown filter type")),
+            });
+        }
+    }
+    Ok(out)
+}
+
+/// Imports a PNG. Paletted images keep their indices where their palette
+/// agrees with `palette`; every other color is matched to the nearest
+/// palette entry.
+fn decode(
+    bytes: &[u8],
+    palette: &Palette,
+) -> Result<(IndexedImage, Option<(i32, i32)>), PngDecodeError> {
+    if !bytes.starts_with(&SIGNATURE) {
+        return Err(PngDecodeError::InvalidSignature);
+    }
+    let mut header = None;
+    let mut plte: Vec<Rgb> = vec![];
+    let mut trns: Vec<u8> = vec![];
+    let mut offsets = None;
+    let mut compressed = vec![];
+    let mut at = SIGNATURE.len();
+    loop {
+        if at + 12 > bytes.len() {
+            return Err(PngDecodeError::Truncated);
+        }
+        let len = be_u32(bytes, at) as usize;
+        let kind: [u8; 4] = bytes[at + 4..at + 8].try_into().unwrap();
+        let end = (at + 8).checked_add(len).ok_or(PngDecodeError::Truncated)?;
+        if end + 4 > bytes.len() {
+            return Err(PngDecodeError::Truncated);
+        }
+        if crc32fast::hash(&bytes[at + 4..end]) != be_u32(bytes, end) {
+            return Err(PngDecodeError::BadChecksum(kind));
+        }
+        let data = &bytes[at + 8..end];
+        match &kind {
+            b"IHDR" if len == 13 => header = Some(data),
+            b"PLTE" => {
+                plte = data
+                    .chunks_exact(3)
+                    .map(|c| Rgb {
+                        r: c[0],
+                        g: c[1],
+                        b: c[2],
+                    })
+                    .collect()
+            }
+            b"tRNS" => trns = data.to_vec(),
+            b"grAb" if len == 8 => {
+                offsets = Some((be_u32(data, 0) as i32, be_u32(data, 4) as i32));
+            }
+            b"IDAT" => compressed.extend(data),
+            b"IEND" => break,
+            _ => {}
+        }
+        at = end + 4;
+    }
+
+    let header = header.ok_or(PngDecodeError::MissingChunk("IHDR"))?;
+    let width = be_u32(header, 0) as usize;
+    let height = be_u32(header, 4) as usize;
+    if width > MAX_DIMENSION || height > MAX_DIMENSION {
+        return Err(PngDecodeError::Unsupported("image size"));
+    }
+    let (depth, color_type) = (header[8], header[9]);
+    if header[12] != 0 {
+        return Err(PngDecodeError::Unsupported("interlacing"));
+    }
+    let channels = match color_type {
+        0 | 3 => 1,
+        2 => 3,
+        4 => 2,
+        6 => 4,
+        _ => return Err(PngDecodeError::Malformed("unknown color type")),
+    };
+    match (color_type, depth) {
+        (0 | 3, 1 | 2 | 4 | 8) | (0 | 2 | 4 | 6, 16) | (_, 8) => {}
+        _ => return Err(PngDecodeError::Unsupported("bit depth")),
+    }
+    if color_type == 3 && plte.is_empty() {
+        return Err(PngDecodeError::MissingChunk("PLTE"));
+    }
+    // Gray and RGB images may name one color, at full sample depth, as
+    // transparent.
+    let key: Option<Vec<u16>> = match color_type {
+        0 | 2 if !trns.is_empty() => {
+            let samples = trns
+                .get(..channels * 2)
+                .ok_or(PngDecodeError::Malformed("short tRNS"))?;
+            Some(
+                samples
+                    .chunks_exact(2)
+                    .map(|c| u16::from_be_bytes([c[0], c[1]]))
+                    .collect(),
+            )
+        }
+        _ => None,
+    };
+    let bits = depth as usize * channels;
+    let stride = (width * bits).div_ceil(8);
+    let mut inflated = vec![];
+    ZlibDecoder::new(&compressed[..])
+        .read_to_end(&mut inflated)
+        .map_err(PngDecodeError::FailedToInflate)?;
+    let data = unfilter(&inflated, stride, bits.div_ceil(8), height)?;
+
+    // Samples at their full depth.
+    let sample = |row: &[u8], index: usize| -> u16 {
+        match depth {
+            16 => u16::from_be_bytes([row[index * 2], row[index * 2 + 1]]),
+            8 => row[index] as u16,
+            _ => {
+                let bit = index * depth as usize;
+                let mask = (1u16 << depth) - 1;
+                (row[bit / 8] as u16 >> (8 - depth as usize - bit % 8)) & mask
+            }
+        }
+    };
+    // A sample scaled to 8 bits.
+    let scale = |v: u16| match depth {
+        1 => (v * 255) as u8,
+        2 => (v * 85) as u8,
+        4 => (v * 17) as u8,
+        16 => (v >> 8) as u8,
+        _ => v as u8,
+    };
+
+    let mut nearest: HashMap<Rgb, u8> = HashMap::new();
+    let mut quantize = |color: Rgb| {
+        *nearest
+            .entry(color)
+            .or_insert_with(|| palette.nearest(color))
+    };
+    let mut image = IndexedImage::new(width, height);
+    for (y, row) in data.chunks(stride.max(1)).take(height).enumerate() {
+        for x in 0..width {
+            let s = |channel: usize| sample(row, x * channels + channel);
+            let (color, alpha) = match color_type {
+                3 => {
+                    let index = s(0) as u8;
+                    let color = *plte
+                        .get(index as usize)
+                        .ok_or(PngDecodeError::Malformed("index past PLTE"))?;
+                    let alpha = trns.get(index as usize).copied().unwrap_or(255);
+                    if alpha >= ALPHA_THRESHOLD && palette.0[index as usize] == color {
+                        image.set(x, y, Some(index));
+                        continue;
+                    }
+                    (color, alpha)
+                }
+                0 | 4 => {
+                    let v = scale(s(0));
+                    let alpha = match color_type {
+                        4 => scale(s(1)),
+                        _ if key.as_deref() == Some(&[s(0)]) => 0,
+                        _ => 255,
+                    };
+                    (Rgb { r: v, g: v, b: v }, alpha)
+                }
+                _ => {
+                    let alpha = match color_type {
+                        6 => scale(s(3)),
+                        _ if key.as_deref() == Some(&[s(0), s(1), s(2)]) => 0,
+                        _ => 255,
+                    };
+                    (
+                        Rgb {
+                            r: scale(s(0)),
+                            g: scale(s(1)),
+                            b: scale(s(2)),
+                        },
+                        alpha,
+                    )
+                }
+            };
+            if alpha >= ALPHA_THRESHOLD {
+                image.set(x, y, Some(quantize(color)));
+            }
+        }
+    }
+    Ok((image, offsets))
+}
+
+impl IndexedImage {
+    pub fn to_png(&self, palette: &Palette) -> Vec<u8> {
+        encode(self, palette, None)
+    }
+
+    /// Any `grAb` offsets are ignored.
+    pub fn from_png(bytes: &[u8], palette: &Palette) -> Result<IndexedImage, PngDecodeError> {
+        decode(bytes, palette).map(|(image, _)| image)
+    }
+}
+
+impl Picture {
+    /// Stores the offsets in a ZDoom `grAb` chunk.
+    pub fn to_png(&self, palette: &Palette) -> Vec<u8> {
+        encode(
+            &self.image,
+            palette,
+            Some((self.left as i32, self.top as i32)),
+        )
+    }
+
+    /// Offsets come from the `grAb` chunk, or are zero without one.
+    pub fn from_png(bytes: &[u8], palette: &Palette) -> Result<Picture, PngDecodeError> {
+        let (image, offsets) = decode(bytes, palette)?;
+        let (left, top) = offsets.unwrap_or((0, 0));
+        let offset = |v: i32| i16::try_from(v).map_err(|_| PngDecodeError::OffsetOutOfRange(v));
+        Ok(Picture {
+            left: offset(left)?,
+            top: offset(top)?,
+            image,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gfx::palette::tests::test_palette;
+
+    fn sample_image() -> IndexedImage {
+        let mut image = IndexedImage::new(5, 4);
+        for y in 0..4 {
+            for x in 0..5 {
+                if x != y {
+                    image.set(x, y, Some((x * 40 + y) as u8));
+                }
+            }
+        }
+        image
+    }
+
+    #[test]
+    fn test_picture_round_trip() {
+        let palette = test_palette();
+        let picture = Picture {
+            left: -3,
+            top: 70,
+            image: sample_image(),
+        };
+        let png = picture.to_png(&palette);
+        assert!(png.windows(4).any(|w| w == b"grAb"));
+        assert_eq!(Picture::from_png(&png, &palette).unwrap(), picture);
+
+        let flat = IndexedImage {
+            width: 3,
+            height: 1,
+            pixels: vec![Some(0), Some(200), Some(255)],
+        };
+        let png = flat.to_png(&palette);
+        assert!(!png.windows(4).any(|w| w == b"tRNS"));
+        assert_eq!(IndexedImage::from_png(&png, &palette).unwrap(), flat);
+    }
+
+    #[test]
+    fn test_every_index_used_falls_back_to_rgba() {
+        let palette = test_palette();
+        let mut image = IndexedImage::new(257, 1);
+        for x in 0..256 {
+            image.set(x, 0, Some(x as u8));
+        }
+        let png = image.to_png(&palette);
+        assert_eq!(png[25], 6);
+        let decoded = IndexedImage::from_png(&png, &palette).unwrap();
+        // Index 128 duplicates black, so it comes back as index 0.
+        image.set(128, 0, Some(0));
+        assert_eq!(decoded, image);
+    }
+
+    #[test]
+    fn test_quantize_truecolor() {
+        let palette = test_palette();
+        // A 2x2 RGBA image, filtered with Sub and Up to exercise unfiltering.
+        let rows: [[u8; 8]; 2] = [
+            [250, 250, 250, 255, 200, 10, 0, 255],
+            [0, 0, 0, 0, 99, 101, 100, 255],
+        ];
+        let mut raw = vec![1];
+        raw.extend(&rows[0][..4]);
+        raw.extend((4..8).map(|i| rows[0][i].wrapping_sub(rows[0][i - 4])));
+        raw.push(2);
+        raw.extend((0..8).map(|i| rows[1][i].wrapping_sub(rows[0][i])));
+        let mut encoder = ZlibEncoder::new(vec![], Compression::default());
+        encoder.write_all(&raw).unwrap();
+
+        let mut png = SIGNATURE.to_vec();
+        let mut header = vec![];
+        header.extend(2u32.to_be_bytes());
+        header.extend(2u32.to_be_bytes());
+        header.extend([8, 6, 0, 0, 0]);
+        write_chunk(&mut png, b"IHDR", &header);
+        write_chunk(&mut png, b"IDAT", &encoder.finish().unwrap());
+        write_chunk(&mut png, b"IEND", &[]);
+
+        let image = IndexedImage::from_png(&png, &palette).unwrap();
+        assert_eq!(image.pixels, [Some(125), Some(128 + 100), None, Some(50)]);
+
+        let last = png.len() - 5;
+        png[last] ^= 1;
+        assert!(matches!(
+            IndexedImage::from_png(&png, &palette),
+            Err(PngDecodeError::BadChecksum(_))
+        ));
+        assert!(matches!(
+            IndexedImage::from_png(b"GIF89a", &palette),
+            Err(PngDecodeError::InvalidSignature)
+        ));
+    }
+
+    /// A PNG of unfiltered rows.
+    fn png(width: u32, depth: u8, color_type: u8, rows: &[&[u8]], trns: &[u8]) -> Vec<u8> {
+        let mut raw = vec![];
+        for row in rows {
+            raw.push(0);
+            raw.extend(*row);
+        }
+        let mut encoder = ZlibEncoder::new(vec![], Compression::default());
+        encoder.write_all(&raw).unwrap();
+        let mut png = SIGNATURE.to_vec();
+        let mut header = vec![];
+        header.extend(width.to_be_bytes());
+        header.extend((rows.len() as u32).to_be_bytes());
+        header.extend([depth, color_type, 0, 0, 0]);
+        write_chunk(&mut png, b"IHDR", &header);
+        if !trns.is_empty() {
+            write_chunk(&mut png, b"tRNS", trns);
+        }
+        write_chunk(&mut png, b"IDAT", &encoder.finish().unwrap());
+        write_chunk(&mut png, b"IEND", &[]);
+        png
+    }
+
+    #[test]
+    fn test_color_keys() {
+        let palette = test_palette();
+        let rgb = png(2, 8, 2, &[&[250, 250, 250, 1, 2, 3]], &[0, 1, 0, 2, 0, 3]);
+        let image = IndexedImage::from_png(&rgb, &palette).unwrap();
+        assert_eq!(image.pixels, [Some(125), None]);
+
+        let gray = png(2, 16, 0, &[&[250, 0, 0, 7]], &[0, 7]);
+        let image = IndexedImage::from_png(&gray, &palette).unwrap();
+        assert_eq!(image.pixels, [Some(125), None]);
+
+        let short = png(1, 8, 2, &[&[1, 2, 3]], &[0, 1]);
+        assert!(matches!(
+            IndexedImage::from_png(&short, &palette),
+            Err(PngDecodeError::Malformed(_))
+        ));
+    }
+
+    #[test]
+    fn test_size_limits() {
+        let palette = test_palette();
+        let mut huge = png(1, 8, 0, &[&[0]], &[]);
+        huge[16..20].copy_from_slice(&0x4000_0000u32.to_be_bytes());
+        let crc = crc32fast::hash(&huge[12..29]);
+        huge[29..33].copy_from_slice(&crc.to_be_bytes());
+        assert!(matches!(
+            IndexedImage::from_png(&huge, &palette),
+            Err(PngDecodeError::Unsupported("image size"))
+        ));
+        assert!(matches!(
+            unfilter(&[], usize::MAX, 1, 2),
+            Err(PngDecodeError::Unsupported("image size"))
+        ));
+    }
+}
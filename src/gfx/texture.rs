@@ -0,0 +1,377 @@
+//! Composite wall textures: PNAMES and TEXTURE1/TEXTURE2.
+
+use crate::raw::{array_at, i16_at, i32_at, u16_at, u8_at};
+use crate::{LumpName, Namespace, Wad};
+
+use super::{GraphicDecodeError, IndexedImage, Picture};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Pnames(pub Vec<LumpName>);
+impl Pnames {
+    pub fn decode(bytes: &[u8]) -> Option<Pnames> {
+        if bytes.len() < 4 {
+            return None;
+        }
+        let count = usize::try_from(i32_at(bytes, 0)).ok()?;
+        let names = bytes[4..].chunks_exact(8).take(count);
+        if names.len() < count {
+            return None;
+        }
+        Some(Pnames(
+            names
+                .map(|name| LumpName::from_short_bytes(name.try_into().unwrap()))
+                .collect(),
+        ))
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut out = (self.0.len() as i32).to_le_bytes().to_vec();
+        for name in &self.0 {
+            out.extend(&name.0[..8]);
+        }
+        out
+    }
+}
+
+/// Strife drops the unused column directory and per-patch step and
+/// colormap fields of the Doom layout.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TextureLayout {
+    Doom,
+    Strife,
+}
+impl TextureLayout {
+    fn header_size(self) -> usize {
+        match self {
+            TextureLayout::Doom => 22,
+            TextureLayout::Strife => 18,
+        }
+    }
+
+    fn patch_size(self) -> usize {
+        match self {
+            TextureLayout::Doom => 10,
+            TextureLayout::Strife => 6,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TexturePatch {
+    pub x: i16,
+    pub y: i16,
+    /// Index into PNAMES.
+    pub patch: u16,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TextureDef {
+    pub name: LumpName,
+    /// Vanilla's unused `masked` field, which ZDoom splits into flags and
+    /// a scale per axis.
+    pub flags: u16,
+    pub scale_x: u8,
+    pub scale_y: u8,
+    pub width: i16,
+    pub height: i16,
+    pub patches: Vec<TexturePatch>,
+}
+impl TextureDef {
+    fn decode(bytes: &[u8], layout: TextureLayout) -> TextureDef {
+        let count_at = layout.header_size() - 2;
+        let count = i16_at(bytes, count_at).max(0) as usize;
+        let patches = (0..count)
+            .map(|i| {
+                let at = layout.header_size() + i * layout.patch_size();
+                TexturePatch {
+                    x: i16_at(bytes, at),
+                    y: i16_at(bytes, at + 2),
+                    patch: u16_at(bytes, at + 4),
+                }
+            })
+            .collect();
+        TextureDef {
+            name: LumpName::from_short_bytes(array_at(bytes, 0)),
+            flags: u16_at(bytes, 8),
+            scale_x: u8_at(bytes, 10),
+            scale_y: u8_at(bytes, 11),
+            width: i16_at(bytes, 12),
+            height: i16_at(bytes, 14),
+            patches,
+        }
+    }
+
+    fn encode(&self, layout: TextureLayout, out: &mut Vec<u8>) {
+        out.extend(&self.name.0[..8]);
+        out.extend(self.flags.to_le_bytes());
+        out.extend([self.scale_x, self.scale_y]);
+        out.extend(self.width.to_le_bytes());
+        out.extend(self.height.to_le_bytes());
+        if layout == TextureLayout::Doom {
+            out.extend(0i32.to_le_bytes());
+        }
+        out.extend((self.patches.len() as i16).to_le_bytes());
+        for patch in &self.patches {
+            out.extend(patch.x.to_le_bytes());
+            out.extend(patch.y.to_le_bytes());
+            out.extend(patch.patch.to_le_bytes());
+            if layout == TextureLayout::Doom {
+                // Step direction and colormap, which vanilla always wrote
+                // as 1 and 0.
+                out.extend(1i16.to_le_bytes());
+                out.extend(0i16.to_le_bytes());
+            }
+        }
+    }
+
+    /// Draws the patches in order over a transparent image. `patch` resolves
+    /// a PNAMES index to its picture.
+    pub fn compose<E>(
+        &self,
+        mut patch: impl FnMut(u16) -> Result<Picture, E>,
+    ) -> Result<IndexedImage, E> {
+        let (width, height) = (self.width.max(0) as usize, self.height.max(0) as usize);
+        let mut image = IndexedImage::new(width, height);
+        for placed in &self.patches {
+            let picture = patch(placed.patch)?;
+            let source = &picture.image;
+            for sy in 0..source.height {
+                let y = placed.y as isize + sy as isize;
+                if y < 0 || y >= height as isize {
+                    continue;
+                }
+                for sx in 0..source.width {
+                    let x = placed.x as isize + sx as isize;
+                    if x < 0 || x >= width as isize {
+                        continue;
+                    }
+                    if let Some(pixel) = source.get(sx, sy) {
+                        image.set(x as usize, y as usize, Some(pixel));
+                    }
+                }
+            }
+        }
+        Ok(image)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TextureList {
+    pub layout: TextureLayout,
+    pub textures: Vec<TextureDef>,
+}
+impl TextureList {
+    /// Detects the layout: Doom when every definition fits it with an empty
+    /// column directory, otherwise Strife.
+    pub fn decode(bytes: &[u8]) -> Option<TextureList> {
+        let doom = TextureList::offsets(bytes, TextureLayout::Doom);
+        let doom_fits = doom
+            .as_ref()
+            .is_some_and(|offsets| offsets.iter().all(|&at| i32_at(bytes, at + 16) == 0));
+        if doom_fits {
+            return TextureList::decode_with(bytes, TextureLayout::Doom);
+        }
+        TextureList::decode_with(bytes, TextureLayout::Strife)
+    }
+
+    pub fn decode_with(bytes: &[u8], layout: TextureLayout) -> Option<TextureList> {
+        let offsets = TextureList::offsets(bytes, layout)?;
+        Some(TextureList {
+            layout,
+            textures: offsets
+                .into_iter()
+                .map(|at| TextureDef::decode(&bytes[at..], layout))
+                .collect(),
+        })
+    }
+
+    /// Definition offsets, if every definition fits the lump in `layout`.
+    fn offsets(bytes: &[u8], layout: TextureLayout) -> Option<Vec<usize>> {
+        if bytes.len() < 4 {
+            return None;
+        }
+        let count = usize::try_from(i32_at(bytes, 0)).ok()?;
+        if bytes.len() < 4 + count.checked_mul(4)? {
+            return None;
+        }
+        let mut offsets = Vec::with_capacity(count);
+        for i in 0..count {
+            let at = usize::try_from(i32_at(bytes, 4 + i * 4)).ok()?;
+            let header = layout.header_size();
+            if at + header > bytes.len() {
+                return None;
+            }
+            let patches = usize::try_from(i16_at(bytes, at + header - 2)).ok()?;
+            if at + header + patches * layout.patch_size() > bytes.len() {
+                return None;
+            }
+            offsets.push(at);
+        }
+        Some(offsets)
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let count = self.textures.len();
+        let mut out = (count as i32).to_le_bytes().to_vec();
+        out.resize(4 + count * 4, 0);
+        for (i, texture) in self.textures.iter().enumerate() {
+            let at = out.len() as i32;
+            out[4 + i * 4..8 + i * 4].copy_from_slice(&at.to_le_bytes());
+            texture.encode(self.layout, &mut out);
+        }
+        out
+    }
+}
+
+impl Wad {
+    pub fn pnames(&self) -> Result<Pnames, GraphicDecodeError> {
+        self.decode_lump("PNAMES", Pnames::decode)
+    }
+
+    /// The definitions of TEXTURE1 followed by those of TEXTURE2, when
+    /// present.
+    pub fn textures(&self) -> Result<Vec<TextureDef>, GraphicDecodeError> {
+        let mut textures = self.decode_lump("TEXTURE1", TextureList::decode)?.textures;
+        if self.find_lump_index("TEXTURE2").is_some() {
+            textures.extend(self.decode_lump("TEXTURE2", TextureList::decode)?.textures);
+        }
+        Ok(textures)
+    }
+
+    /// Renders a wall texture. As in vanilla, the first definition with the
+    /// name wins, and patches are looked up between the `P_START` markers
+    /// before the rest of the directory.
+    pub fn compose_texture(&self, name: &str) -> Result<IndexedImage, GraphicDecodeError> {
+        let wanted = LumpName::from_string(name.into())
+            .map_err(|_| GraphicDecodeError::NoSuchLump(name.into()))?;
+        let textures = self.textures()?;
+        let texture = textures
+            .iter()
+            .find(|texture| texture.name == wanted)
+            .ok_or_else(|| GraphicDecodeError::NoSuchLump(name.into()))?;
+        let pnames = self.pnames()?;
+        texture.compose(|index| {
+            let patch = pnames
+                .0
+                .get(index as usize)
+                .ok_or_else(|| GraphicDecodeError::InvalidLump("PNAMES".into()))?
+                .to_string();
+            let index = self
+                .find_in_namespace_index(&patch, Namespace::Patches)
+                .or_else(|| self.find_lump_index(&patch))
+                .ok_or_else(|| GraphicDecodeError::NoSuchLump(patch.clone()))?;
+            let data = self
+                .read_lump(index)
+                .map_err(GraphicDecodeError::FailedToReadLump)?;
+            Picture::decode(&data).ok_or(GraphicDecodeError::InvalidLump(patch))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::decode;
+    use crate::WadBuilder;
+
+    fn list(layout: TextureLayout) -> TextureList {
+        TextureList {
+            layout,
+            textures: vec![
+                TextureDef {
+                    name: LumpName::known("WALL"),
+                    flags: 0,
+                    scale_x: 0,
+                    scale_y: 0,
+                    width: 4,
+                    height: 2,
+                    patches: vec![
+                        TexturePatch {
+                            x: 0,
+                            y: 0,
+                            patch: 0,
+                        },
+                        TexturePatch {
+                            x: 2,
+                            y: -1,
+                            patch: 1,
+                        },
+                    ],
+                },
+                TextureDef {
+                    name: LumpName::known("EMPTY"),
+                    flags: 0x8000,
+                    scale_x: 2,
+                    scale_y: 2,
+                    width: 64,
+                    height: 128,
+                    patches: vec![],
+                },
+            ],
+        }
+    }
+
+    #[test]
+    fn test_round_trip_and_layout_detection() {
+        for layout in [TextureLayout::Doom, TextureLayout::Strife] {
+            let textures = list(layout);
+            let bytes = textures.encode();
+            assert_eq!(TextureList::decode(&bytes), Some(textures));
+        }
+        assert_eq!(TextureList::decode(&[1, 0, 0, 0, 200, 0, 0, 0]), None);
+
+        let pnames = Pnames(vec![LumpName::known("PATCH0"), LumpName::known("PATCH1")]);
+        assert_eq!(Pnames::decode(&pnames.encode()), Some(pnames));
+    }
+
+    #[test]
+    fn test_compose() {
+        let solid = |value: u8, width: usize, height: usize| Picture {
+            left: 0,
+            top: 0,
+            image: IndexedImage {
+                width,
+                height,
+                pixels: vec![Some(value); width * height],
+            },
+        };
+        let mut holey = solid(9, 2, 2);
+        holey.image.set(1, 1, None);
+        let pnames = Pnames(vec![LumpName::known("PATCH0"), LumpName::known("PATCH1")]);
+        let wad = decode(
+            &WadBuilder::pwad()
+                .lump(LumpName::known("PNAMES"), pnames.encode())
+                .lump(
+                    LumpName::known("TEXTURE1"),
+                    list(TextureLayout::Doom).encode(),
+                )
+                .lump(LumpName::known("PATCH1"), vec![])
+                .lump(LumpName::known("P_START"), vec![])
+                .lump(LumpName::known("PATCH0"), solid(1, 3, 2).encode().unwrap())
+                .lump(LumpName::known("PATCH1"), holey.encode().unwrap())
+                .lump(LumpName::known("P_END"), vec![]),
+        );
+        let image = wad.compose_texture("wall").unwrap();
+        assert_eq!(
+            image.pixels,
+            [
+                Some(1),
+                Some(1),
+                Some(9),
+                None,
+                Some(1),
+                Some(1),
+                Some(1),
+                None
+            ]
+        );
+        assert!(matches!(
+            wad.compose_texture("NOSUCH"),
+            Err(GraphicDecodeError::NoSuchLump(_))
+        ));
+        assert_eq!(
+            wad.compose_texture("EMPTY").unwrap(),
+            IndexedImage::new(64, 128)
+        );
+    }
+}
@@ -1,49 +1,121 @@
-use std::{fs::File, io::Read, mem::size_of, os::unix::prelude::FileExt, path::Path};
+use std::{
+    cell::RefCell,
+    fs::File,
+    io::{Cursor, Read, Seek, SeekFrom},
+    path::{Path, PathBuf},
+};
 
-#[derive(Debug)]
-pub struct Location(i32);
+mod builder;
+mod edit;
+pub mod gfx;
+mod lookup;
+pub mod map;
+mod namespace;
+mod raw;
+mod stack;
+#[cfg(test)]
+mod testutil;
+mod validate;
 
-#[derive(Debug)]
-pub struct Size(i32);
+pub use builder::{Lump, WadBuilder, WadEncodeError};
+pub use namespace::Namespace;
+use raw::{RawEntry, RawHeader, RawLongEntry};
+pub use stack::{ResourceRef, WadStack};
+
+#[derive(Debug, Clone, Copy)]
+pub struct Location(pub i32);
+
+#[derive(Debug, Clone, Copy)]
+pub struct Size(pub i32);
+impl Size {
+    /// The size of `data`, saturated; anything that large fails to save
+    /// anyway.
+    pub(crate) fn of(data: &[u8]) -> Size {
+        Size(i32::try_from(data.len()).unwrap_or(i32::MAX))
+    }
+}
 
+#[derive(Debug)]
 pub enum LumpNameError {
     TooLarge,
 }
 
-pub struct LumpName([u8; 8]);
+/// Doom directories store 8 bytes of name, WAD2/WAD3 directories store 16.
+#[derive(Clone, Copy)]
+pub struct LumpName([u8; 16]);
 impl LumpName {
     pub fn from_string(str: String) -> Result<LumpName, LumpNameError> {
-        if str.len() > 8 {
+        LumpName::from_str_with_limit(&str, 8)
+    }
+
+    pub fn from_long_string(str: String) -> Result<LumpName, LumpNameError> {
+        LumpName::from_str_with_limit(&str, 16)
+    }
+
+    fn from_str_with_limit(str: &str, limit: usize) -> Result<LumpName, LumpNameError> {
+        if str.len() > limit {
             Err(LumpNameError::TooLarge)
         } else {
             use std::io::Write;
-            let mut buf = [0; 8];
+            let mut buf = [0; 16];
             let mut w: &mut [u8] = &mut buf;
-            w.write(str.as_bytes()).unwrap();
+            w.write_all(str.as_bytes()).unwrap();
             Ok(LumpName(buf))
         }
     }
+
+    /// For names fixed in the source, which are known to fit.
+    pub(crate) fn known(name: &str) -> LumpName {
+        LumpName::from_str_with_limit(name, 8).unwrap()
+    }
+
+    pub(crate) fn from_short_bytes(bytes: [u8; 8]) -> LumpName {
+        let mut buf = [0; 16];
+        buf[..8].copy_from_slice(&bytes);
+        LumpName(buf)
+    }
+}
+impl LumpName {
+    /// Uppercased bytes up to the first NUL; WAD3 names often carry garbage
+    /// after the terminator.
+    fn key(&self) -> [u8; 16] {
+        let mut key = [0; 16];
+        for (k, &b) in key.iter_mut().zip(self.0.iter().take_while(|&&b| b != 0)) {
+            *k = b.to_ascii_uppercase();
+        }
+        key
+    }
+}
+impl PartialEq for LumpName {
+    fn eq(&self, other: &Self) -> bool {
+        self.key() == other.key()
+    }
+}
+impl Eq for LumpName {}
+impl std::hash::Hash for LumpName {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.key().hash(state)
+    }
 }
-impl ToString for LumpName {
-    fn to_string(&self) -> String {
-        String::from_utf8_lossy(&self.0)
-            .trim_matches(char::from(0))
-            .into()
+impl std::fmt::Display for LumpName {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let end = self.0.iter().position(|&b| b == 0).unwrap_or(self.0.len());
+        f.write_str(&String::from_utf8_lossy(&self.0[..end]))
     }
 }
 impl std::fmt::Debug for LumpName {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        f.write_str(format!("LumpName({})", self.to_string()).as_str())
+        write!(f, "LumpName({})", self)
     }
 }
 
-#[derive(Debug)]
-pub struct EntryType(u8);
+#[derive(Debug, Clone, Copy)]
+pub struct EntryType(pub u8);
 
-#[derive(Debug)]
-pub struct Compression(u8);
+#[derive(Debug, Clone, Copy)]
+pub struct Compression(pub u8);
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Entry {
     pub start: Location,
     pub size: Size,
@@ -52,14 +124,80 @@ pub struct Entry {
     pub compression: Compression,
     pub padding: i16,
     pub name: LumpName,
+    pub(crate) staged: Option<Vec<u8>>,
 }
+impl Entry {
+    pub(crate) fn new(name: LumpName, start: i32, size: i32) -> Entry {
+        Entry {
+            start: Location(start),
+            size: Size(size),
+            real_size: Size(size),
+            kind: EntryType(0_u8),
+            compression: Compression(0_u8),
+            padding: 0,
+            name,
+            staged: None,
+        }
+    }
 
-#[derive(Debug)]
+    /// An entry for data not yet written, sized to match it. `start` stays 0
+    /// until the WAD is saved.
+    pub(crate) fn staged(name: LumpName, data: Vec<u8>) -> Entry {
+        let mut entry = Entry::new(name, 0, Size::of(&data).0);
+        entry.staged = Some(data);
+        entry
+    }
+
+    pub fn is_modified(&self) -> bool {
+        self.staged.is_some()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct Signature([u8; 4]);
-impl ToString for Signature {
-    fn to_string(&self) -> String {
-        String::from_utf8_lossy(&self.0).into_owned()
+impl Signature {
+    pub const IWAD: Signature = Signature(*b"IWAD");
+    pub const PWAD: Signature = Signature(*b"PWAD");
+    pub const WAD2: Signature = Signature(*b"WAD2");
+    pub const WAD3: Signature = Signature(*b"WAD3");
+
+    pub fn kind(&self) -> WadKind {
+        match &self.0 {
+            b"IWAD" => WadKind::Iwad,
+            b"PWAD" => WadKind::Pwad,
+            b"WAD2" => WadKind::Wad2,
+            b"WAD3" => WadKind::Wad3,
+            _ => WadKind::Unknown,
+        }
     }
+
+    /// Quake WAD2 and Half-Life WAD3 use 32-byte directory entries with
+    /// 16-byte names instead of Doom's 16-byte entries.
+    pub fn has_long_entries(&self) -> bool {
+        matches!(self.kind(), WadKind::Wad2 | WadKind::Wad3)
+    }
+
+    pub fn entry_size(&self) -> usize {
+        if self.has_long_entries() {
+            RawLongEntry::SIZE
+        } else {
+            RawEntry::SIZE
+        }
+    }
+}
+impl std::fmt::Display for Signature {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&String::from_utf8_lossy(&self.0))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WadKind {
+    Iwad,
+    Pwad,
+    Wad2,
+    Wad3,
+    Unknown,
 }
 
 #[derive(Debug)]
@@ -67,71 +205,216 @@ pub enum WadDecodeError {
     FailedToOpenFile(std::io::Error),
     FailedToReadHeader(std::io::Error),
     CouldNotDecodeHeader,
+    InvalidSignature(Signature),
     FailedToReadDirectory(std::io::Error),
     CouldNotDecodeDirectory,
+    NegativeLumpCount(i32),
+    DirectoryOutOfBounds {
+        dir_loc: i32,
+        count: i32,
+        file_len: u64,
+    },
+    LumpOutOfBounds {
+        index: usize,
+        start: i32,
+        size: i32,
+        file_len: u64,
+    },
+    OverlappingLumps {
+        index: usize,
+        other: usize,
+    },
 }
 
 #[derive(Debug)]
+pub enum LumpReadError {
+    NoSuchLump(usize),
+    InvalidBounds,
+    FailedToReadLump(std::io::Error),
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct DecodeOptions {
+    /// Report an unknown signature or a malformed directory as warnings on
+    /// the decoded `Wad` instead of failing. Unknown signatures are decoded
+    /// with the Doom directory layout.
+    pub lenient: bool,
+}
+impl DecodeOptions {
+    pub fn lenient() -> DecodeOptions {
+        DecodeOptions { lenient: true }
+    }
+}
+
+pub trait ReadSeek: Read + Seek {}
+impl<T: Read + Seek> ReadSeek for T {}
+
 pub struct Wad {
     pub signature: Signature,
     pub directory: Vec<Entry>,
+    reader: RefCell<Box<dyn ReadSeek>>,
+    path: Option<PathBuf>,
+    warnings: Vec<WadDecodeError>,
+    index: lookup::LumpIndex,
+    namespaces: Vec<Option<Namespace>>,
+}
+impl std::fmt::Debug for Wad {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Wad")
+            .field("signature", &self.signature)
+            .field("directory", &self.directory)
+            .field("path", &self.path)
+            .field("warnings", &self.warnings)
+            .finish_non_exhaustive()
+    }
 }
 impl Wad {
     pub fn from_file_path<P>(wad_path: P) -> Result<Wad, WadDecodeError>
     where
         P: AsRef<Path>,
     {
-        #[repr(C, packed)]
-        struct RawHeader {
-            signature: [u8; 4],
-            count: i32,
-            dir_loc: i32,
+        Wad::from_file_path_with(wad_path, &DecodeOptions::default())
+    }
+
+    pub fn from_file_path_with<P>(
+        wad_path: P,
+        options: &DecodeOptions,
+    ) -> Result<Wad, WadDecodeError>
+    where
+        P: AsRef<Path>,
+    {
+        let fin = File::open(&wad_path).map_err(WadDecodeError::FailedToOpenFile)?;
+        let mut wad = Wad::from_reader_with(fin, options)?;
+        wad.path = Some(wad_path.as_ref().to_path_buf());
+        Ok(wad)
+    }
+
+    pub fn path(&self) -> Option<&Path> {
+        self.path.as_deref()
+    }
+
+    pub fn kind(&self) -> WadKind {
+        self.signature.kind()
+    }
+
+    /// Problems tolerated while decoding with `DecodeOptions::lenient`.
+    pub fn warnings(&self) -> &[WadDecodeError] {
+        &self.warnings
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Wad, WadDecodeError> {
+        Wad::from_bytes_with(bytes, &DecodeOptions::default())
+    }
+
+    pub fn from_bytes_with(bytes: &[u8], options: &DecodeOptions) -> Result<Wad, WadDecodeError> {
+        Wad::from_reader_with(Cursor::new(bytes.to_vec()), options)
+    }
+
+    pub fn from_reader<R>(reader: R) -> Result<Wad, WadDecodeError>
+    where
+        R: Read + Seek + 'static,
+    {
+        Wad::from_reader_with(reader, &DecodeOptions::default())
+    }
+
+    pub fn from_reader_with<R>(
+        mut reader: R,
+        options: &DecodeOptions,
+    ) -> Result<Wad, WadDecodeError>
+    where
+        R: Read + Seek + 'static,
+    {
+        let mut raw_header = [0; RawHeader::SIZE];
+        reader
+            .seek(SeekFrom::Start(0))
+            .and_then(|_| reader.read_exact(&mut raw_header))
+            .map_err(WadDecodeError::FailedToReadHeader)?;
+        let raw_header =
+            RawHeader::decode(&raw_header).ok_or(WadDecodeError::CouldNotDecodeHeader)?;
+        let mut directory = vec![];
+        let signature = Signature(raw_header.signature);
+        let mut problems = vec![];
+        if signature.kind() == WadKind::Unknown {
+            problems.push(WadDecodeError::InvalidSignature(signature));
         }
-        let mut fin = File::open(wad_path).map_err(|err| WadDecodeError::FailedToOpenFile(err))?;
-        let mut raw_header: [u8; size_of::<RawHeader>()] = [0; size_of::<RawHeader>()];
-        fin.read_exact(&mut raw_header)
-            .map_err(|err| WadDecodeError::FailedToReadHeader(err))?;
-        let (_, raw_header, _) = unsafe { raw_header.align_to::<RawHeader>() };
-        let raw_header = raw_header.get(0);
-        if raw_header.is_none() {
-            return Err(WadDecodeError::CouldNotDecodeHeader);
+        let file_len = reader
+            .seek(SeekFrom::End(0))
+            .map_err(WadDecodeError::FailedToReadDirectory)?;
+        let count = validate::directory_count(
+            raw_header.count,
+            raw_header.dir_loc,
+            signature.entry_size(),
+            file_len,
+            &mut problems,
+        );
+        if !options.lenient && !problems.is_empty() {
+            return Err(problems.remove(0));
         }
-        let raw_header = raw_header.unwrap();
-        let mut wad = Wad {
-            signature: Signature(raw_header.signature.clone()),
-            directory: vec![],
-        };
-        #[repr(C, packed)]
-        struct RawEntry {
-            file_pos: i32,
-            size: i32,
-            name: [u8; 8],
+        let mut entry_buf = vec![0; signature.entry_size()];
+        if count > 0 {
+            reader
+                .seek(SeekFrom::Start(raw_header.dir_loc as u64))
+                .map_err(WadDecodeError::FailedToReadDirectory)?;
         }
-        const ENTRY_SIZE: usize = size_of::<RawEntry>();
-        let mut entry_buf: [u8; ENTRY_SIZE] = [0; ENTRY_SIZE];
-        for i in 0..raw_header.count as u64 {
-            fin.read_exact_at(
-                &mut entry_buf,
-                i * ENTRY_SIZE as u64 + (raw_header.dir_loc as u64),
-            )
-            .map_err(|err| WadDecodeError::FailedToReadDirectory(err))?;
-            let (_, raw_entry, _) = unsafe { entry_buf.align_to::<RawEntry>() };
-            let raw_entry = raw_entry.get(0);
-            if let Some(entry) = raw_entry {
-                wad.directory.push(Entry {
-                    start: Location(entry.file_pos),
-                    size: Size(entry.size),
-                    real_size: Size(entry.size),
-                    kind: EntryType(0_u8),
-                    compression: Compression(0_u8),
-                    padding: 0,
-                    name: LumpName(entry.name),
-                })
+        for _ in 0..count {
+            reader
+                .read_exact(&mut entry_buf)
+                .map_err(WadDecodeError::FailedToReadDirectory)?;
+            let entry = if signature.has_long_entries() {
+                RawLongEntry::decode(&entry_buf).map(Entry::from)
             } else {
-                return Err(WadDecodeError::CouldNotDecodeDirectory);
+                RawEntry::decode(&entry_buf).map(Entry::from)
+            };
+            match entry {
+                Some(entry) => directory.push(entry),
+                None => return Err(WadDecodeError::CouldNotDecodeDirectory),
             }
         }
-        Ok(wad)
+        problems.extend(validate::check_entries(&directory, file_len));
+        if !options.lenient && !problems.is_empty() {
+            return Err(problems.remove(0));
+        }
+        Ok(Wad {
+            signature,
+            index: lookup::build_index(&directory),
+            namespaces: namespace::build_namespaces(&directory),
+            directory,
+            reader: RefCell::new(Box::new(reader)),
+            path: None,
+            warnings: problems,
+        })
+    }
+
+    pub fn lump_data(&self, entry: &Entry) -> Result<Vec<u8>, LumpReadError> {
+        if let Some(data) = &entry.staged {
+            return Ok(data.clone());
+        }
+        if entry.start.0 < 0 || entry.size.0 < 0 {
+            return Err(LumpReadError::InvalidBounds);
+        }
+        let mut reader = self.reader.borrow_mut();
+        // Lenient decoding keeps out-of-bounds entries, so check before
+        // allocating what may be a corrupt size.
+        let file_len = reader
+            .seek(SeekFrom::End(0))
+            .map_err(LumpReadError::FailedToReadLump)?;
+        if entry.start.0 as u64 + entry.size.0 as u64 > file_len {
+            return Err(LumpReadError::InvalidBounds);
+        }
+        let mut data = vec![0; entry.size.0 as usize];
+        reader
+            .seek(SeekFrom::Start(entry.start.0 as u64))
+            .and_then(|_| reader.read_exact(&mut data))
+            .map_err(LumpReadError::FailedToReadLump)?;
+        Ok(data)
+    }
+
+    pub fn read_lump(&self, index: usize) -> Result<Vec<u8>, LumpReadError> {
+        let entry = self
+            .directory
+            .get(index)
+            .ok_or(LumpReadError::NoSuchLump(index))?;
+        self.lump_data(entry)
     }
 }
 
@@ -142,11 +425,105 @@ mod tests {
     #[test]
     fn test_decode_doom_wad() {
         let wad = Wad::from_file_path("DOOM.WAD").unwrap();
-        let e1m1 = wad.directory.get(6).unwrap().name.to_string();
+        let e1m1 = wad.find_map("E1M1").expect("E1M1 was not found");
+        assert_eq!(e1m1.format, map::MapFormat::Doom);
         assert!(
-            e1m1 == "E1M1".to_string(),
-            "The 6th name was not E1M1, found: {:?}",
+            e1m1.things.is_some() && e1m1.linedefs.is_some() && e1m1.sectors.is_some(),
+            "E1M1 is missing lumps, found: {:?}",
             e1m1
         );
     }
+
+    fn handcrafted_pwad() -> Vec<u8> {
+        let mut bytes = b"PWAD".to_vec();
+        bytes.extend(2_i32.to_le_bytes());
+        bytes.extend(17_i32.to_le_bytes());
+        bytes.extend(b"hello");
+        for (start, size, name) in [(12_i32, 5_i32, b"GREET\0\0\0"), (0, 0, b"EMPTY\0\0\0")] {
+            bytes.extend(start.to_le_bytes());
+            bytes.extend(size.to_le_bytes());
+            bytes.extend(name);
+        }
+        bytes
+    }
+
+    #[test]
+    fn test_read_lump_data() {
+        let path = std::env::temp_dir().join("wad_rs_test_read_lump_data.wad");
+        std::fs::write(&path, handcrafted_pwad()).unwrap();
+        let wad = Wad::from_file_path(&path).unwrap();
+        assert_eq!(wad.read_lump(0).unwrap(), b"hello");
+        assert_eq!(wad.lump_data(&wad.directory[1]).unwrap(), b"");
+        assert!(matches!(
+            wad.read_lump(2),
+            Err(LumpReadError::NoSuchLump(2))
+        ));
+        std::fs::remove_file(path).unwrap();
+    }
+    #[test]
+    fn test_from_bytes_and_reader() {
+        let bytes = handcrafted_pwad();
+        let wad = Wad::from_bytes(&bytes).unwrap();
+        assert_eq!(wad.signature.to_string(), "PWAD");
+        assert_eq!(wad.directory[0].name.to_string(), "GREET");
+        assert_eq!(wad.read_lump(0).unwrap(), b"hello");
+        let wad = Wad::from_reader(std::io::Cursor::new(bytes)).unwrap();
+        assert_eq!(wad.directory[1].name.to_string(), "EMPTY");
+        assert!(matches!(
+            Wad::from_bytes(b"PWAD"),
+            Err(WadDecodeError::FailedToReadHeader(_))
+        ));
+    }
+
+    #[test]
+    fn test_signature_validation() {
+        let mut bytes = handcrafted_pwad();
+        assert_eq!(Wad::from_bytes(&bytes).unwrap().kind(), WadKind::Pwad);
+        bytes[..4].copy_from_slice(b"JUNK");
+        match Wad::from_bytes(&bytes) {
+            Err(WadDecodeError::InvalidSignature(signature)) => {
+                assert_eq!(signature.to_string(), "JUNK")
+            }
+            other => panic!("expected InvalidSignature, found {:?}", other),
+        }
+        let wad = Wad::from_bytes_with(&bytes, &DecodeOptions::lenient()).unwrap();
+        assert_eq!(wad.kind(), WadKind::Unknown);
+        assert_eq!(wad.read_lump(0).unwrap(), b"hello");
+    }
+
+    #[test]
+    fn test_directory_validation() {
+        let mut bytes = handcrafted_pwad();
+        bytes[4..8].copy_from_slice(&1000_i32.to_le_bytes());
+        assert!(matches!(
+            Wad::from_bytes(&bytes),
+            Err(WadDecodeError::DirectoryOutOfBounds { count: 1000, .. })
+        ));
+        bytes[4..8].copy_from_slice(&2_i32.to_le_bytes());
+        bytes[21..25].copy_from_slice(&500_i32.to_le_bytes());
+        assert!(matches!(
+            Wad::from_bytes(&bytes),
+            Err(WadDecodeError::LumpOutOfBounds {
+                index: 0,
+                size: 500,
+                ..
+            })
+        ));
+        let wad = Wad::from_bytes_with(&bytes, &DecodeOptions::lenient()).unwrap();
+        assert_eq!(wad.directory.len(), 2);
+        assert!(matches!(
+            wad.warnings(),
+            [WadDecodeError::LumpOutOfBounds { index: 0, .. }]
+        ));
+        bytes[21..25].copy_from_slice(&i32::MAX.to_le_bytes());
+        let wad = Wad::from_bytes_with(&bytes, &DecodeOptions::lenient()).unwrap();
+        assert!(matches!(
+            wad.read_lump(0),
+            Err(LumpReadError::InvalidBounds)
+        ));
+        assert!(Wad::from_bytes(&handcrafted_pwad())
+            .unwrap()
+            .warnings()
+            .is_empty());
+    }
 }
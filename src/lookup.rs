@@ -0,0 +1,83 @@
+use std::collections::HashMap;
+
+use crate::{namespace::build_namespaces, Entry, LumpName, Wad};
+
+pub(crate) type LumpIndex = HashMap<LumpName, Vec<usize>>;
+
+pub(crate) fn build_index(directory: &[Entry]) -> LumpIndex {
+    let mut index = LumpIndex::new();
+    for (i, entry) in directory.iter().enumerate() {
+        index.entry(entry.name).or_default().push(i);
+    }
+    index
+}
+
+impl Wad {
+    /// Rebuilds the name index and namespaces. Only needed after mutating
+    /// `directory` directly; the editing methods keep them up to date.
+    pub fn reindex(&mut self) {
+        self.index = build_index(&self.directory);
+        self.namespaces = build_namespaces(&self.directory);
+    }
+
+    /// Directory positions of every lump called `name`, in directory order.
+    /// Names are compared case-insensitively.
+    pub fn find_all_indices(&self, name: &str) -> &[usize] {
+        LumpName::from_long_string(name.into())
+            .ok()
+            .and_then(|name| self.index.get(&name))
+            .map(Vec::as_slice)
+            .unwrap_or(&[])
+    }
+
+    pub fn find_all(&self, name: &str) -> impl Iterator<Item = &Entry> {
+        self.find_all_indices(name)
+            .iter()
+            .map(|&i| &self.directory[i])
+    }
+
+    /// Follows the Doom engine rule: when several lumps share a name, the
+    /// last one in the directory wins.
+    pub fn find_lump_index(&self, name: &str) -> Option<usize> {
+        self.find_all_indices(name).last().copied()
+    }
+
+    pub fn find_lump(&self, name: &str) -> Option<&Entry> {
+        self.find_lump_index(name).map(|i| &self.directory[i])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testutil::{decode, name};
+    use crate::WadBuilder;
+
+    #[test]
+    fn test_last_lump_wins() {
+        let mut wad = decode(
+            &WadBuilder::pwad()
+                .lump(name("PLAYPAL"), vec![1])
+                .lump(name("THINGS"), vec![2])
+                .lump(name("playpal"), vec![3]),
+        );
+        assert_eq!(wad.find_all_indices("PlayPal"), [0, 2]);
+        assert_eq!(wad.find_all("PLAYPAL").count(), 2);
+        let entry = wad.find_lump("PLAYPAL").unwrap();
+        assert_eq!(wad.lump_data(entry).unwrap(), [3]);
+        assert!(wad.find_lump("COLORMAP").is_none());
+        assert!(wad.find_lump("MUCH_TOO_LONG_A_NAME").is_none());
+
+        wad.remove_lump(2);
+        assert_eq!(wad.find_lump_index("PLAYPAL"), Some(0));
+        wad.push_lump(name("THINGS"), vec![4]);
+        assert_eq!(wad.find_lump_index("things"), Some(2));
+        wad.rename_lump(2, name("LINEDEFS"));
+        assert_eq!(wad.find_lump_index("THINGS"), Some(1));
+    }
+
+    #[test]
+    fn test_lump_name_ignores_case() {
+        assert_eq!(name("e1m1"), name("E1M1"));
+        assert_ne!(name("E1M1"), name("E1M10"));
+    }
+}
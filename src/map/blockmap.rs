@@ -0,0 +1,297 @@
+//! BLOCKMAP decoding, generation and encoding.
+
+use std::collections::HashMap;
+
+use crate::raw::{i16_at, u16_at};
+
+use super::{Map, MapEncodeError};
+
+/// Side of the square blocks, in map units.
+pub const BLOCK_SIZE: i32 = 128;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum BlockmapCompression {
+    /// Every block gets its own list.
+    None,
+    /// Blocks with identical lists share one copy, which keeps large maps
+    /// within the offsets vanilla can reach.
+    #[default]
+    Deduplicate,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Blockmap {
+    pub origin_x: i16,
+    pub origin_y: i16,
+    pub columns: u16,
+    pub rows: u16,
+    /// Linedef indices per block, row by row from the origin, without the
+    /// leading 0 and trailing -1 of the lump format.
+    pub blocks: Vec<Vec<u16>>,
+    pub compression: BlockmapCompression,
+}
+impl Blockmap {
+    pub fn decode(bytes: &[u8]) -> Option<Blockmap> {
+        if bytes.len() < 8 {
+            return None;
+        }
+        let columns = u16_at(bytes, 4);
+        let rows = u16_at(bytes, 6);
+        let count = columns as usize * rows as usize;
+        if bytes.len() < 8 + count * 2 {
+            return None;
+        }
+        let mut blocks = Vec::with_capacity(count);
+        let mut offsets = Vec::with_capacity(count);
+        for i in 0..count {
+            let offset = u16_at(bytes, 8 + i * 2) as usize * 2;
+            offsets.push(offset);
+            let mut list = vec![];
+            let mut at = offset;
+            loop {
+                if at + 2 > bytes.len() {
+                    return None;
+                }
+                match u16_at(bytes, at) {
+                    0xFFFF => break,
+                    0 if at == offset => {}
+                    linedef => list.push(linedef),
+                }
+                at += 2;
+            }
+            blocks.push(list);
+        }
+        offsets.sort_unstable();
+        offsets.dedup();
+        Some(Blockmap {
+            origin_x: i16_at(bytes, 0),
+            origin_y: i16_at(bytes, 2),
+            columns,
+            rows,
+            blocks,
+            compression: if offsets.len() < count {
+                BlockmapCompression::Deduplicate
+            } else {
+                BlockmapCompression::None
+            },
+        })
+    }
+
+    /// Fails when a list starts past word 32767, since vanilla reads the
+    /// offsets as signed shorts.
+    pub fn encode(&self) -> Result<Vec<u8>, MapEncodeError> {
+        let header_words = 4 + self.blocks.len();
+        let mut lists: Vec<u16> = vec![];
+        let mut shared: HashMap<&[u16], usize> = HashMap::new();
+        let mut offsets = Vec::with_capacity(self.blocks.len());
+        for block in &self.blocks {
+            let reuse = match self.compression {
+                BlockmapCompression::Deduplicate => shared.get(block.as_slice()).copied(),
+                BlockmapCompression::None => None,
+            };
+            let offset = match reuse {
+                Some(offset) => offset,
+                None => {
+                    let offset = header_words + lists.len();
+                    lists.push(0);
+                    lists.extend(block);
+                    lists.push(0xFFFF);
+                    shared.insert(block, offset);
+                    offset
+                }
+            };
+            let offset = i16::try_from(offset).map_err(|_| MapEncodeError::BlockmapTooLarge)?;
+            offsets.push(offset as u16);
+        }
+        let mut out = Vec::with_capacity((header_words + lists.len()) * 2);
+        out.extend(self.origin_x.to_le_bytes());
+        out.extend(self.origin_y.to_le_bytes());
+        out.extend(self.columns.to_le_bytes());
+        out.extend(self.rows.to_le_bytes());
+        for word in offsets.into_iter().chain(lists) {
+            out.extend(word.to_le_bytes());
+        }
+        Ok(out)
+    }
+
+    /// Linedefs listed in the block containing map point `(x, y)`, if it is
+    /// inside the blockmap.
+    pub fn block_at(&self, x: i32, y: i32) -> Option<&[u16]> {
+        let column = (x - self.origin_x as i32).div_euclid(BLOCK_SIZE);
+        let row = (y - self.origin_y as i32).div_euclid(BLOCK_SIZE);
+        if column < 0 || row < 0 || column >= self.columns as i32 || row >= self.rows as i32 {
+            return None;
+        }
+        self.blocks
+            .get(row as usize * self.columns as usize + column as usize)
+            .map(Vec::as_slice)
+    }
+
+    /// Generates a blockmap for `map`'s linedefs. The origin sits 8 units
+    /// below and left of the lowest vertex, as classic node builders do.
+    pub fn build(map: &Map, compression: BlockmapCompression) -> Result<Blockmap, MapEncodeError> {
+        let lines = map.linedefs.geometry();
+        let point = |v: u16| {
+            map.vertexes
+                .get(v as usize)
+                .map(|v| (v.x as i32, v.y as i32))
+        };
+        let segments: Vec<Option<(Point, Point)>> = lines
+            .iter()
+            .map(|l| Some((point(l.v1)?, point(l.v2)?)))
+            .collect();
+        let points = segments.iter().flatten().flat_map(|&(a, b)| [a, b]);
+        let (mut min_x, mut min_y, mut max_x, mut max_y) = (0, 0, 0, 0);
+        for (i, (x, y)) in points.enumerate() {
+            if i == 0 {
+                (min_x, min_y, max_x, max_y) = (x, y, x, y);
+            }
+            min_x = min_x.min(x);
+            min_y = min_y.min(y);
+            max_x = max_x.max(x);
+            max_y = max_y.max(y);
+        }
+        let origin_x = min_x - 8;
+        let origin_y = min_y - 8;
+        let columns = (max_x - origin_x) / BLOCK_SIZE + 1;
+        let rows = (max_y - origin_y) / BLOCK_SIZE + 1;
+        let mut blocks = vec![vec![]; (columns * rows) as usize];
+        for (index, segment) in segments.iter().enumerate() {
+            let Some((a, b)) = *segment else { continue };
+            // 0xFFFF ends a list, so it cannot name a linedef.
+            let linedef = u16::try_from(index)
+                .ok()
+                .filter(|&linedef| linedef != 0xFFFF)
+                .ok_or(MapEncodeError::IndexOutOfRange {
+                    lump: "BLOCKMAP",
+                    index,
+                })?;
+            let first_column = (a.0.min(b.0) - origin_x) / BLOCK_SIZE;
+            let last_column = (a.0.max(b.0) - origin_x) / BLOCK_SIZE;
+            let first_row = (a.1.min(b.1) - origin_y) / BLOCK_SIZE;
+            let last_row = (a.1.max(b.1) - origin_y) / BLOCK_SIZE;
+            for row in first_row..=last_row {
+                for column in first_column..=last_column {
+                    let left = origin_x + column * BLOCK_SIZE;
+                    let bottom = origin_y + row * BLOCK_SIZE;
+                    if crosses_box(a, b, left, bottom) {
+                        blocks[(row * columns + column) as usize].push(linedef);
+                    }
+                }
+            }
+        }
+        Ok(Blockmap {
+            origin_x: origin_x as i16,
+            origin_y: origin_y as i16,
+            columns: columns as u16,
+            rows: rows as u16,
+            blocks,
+            compression,
+        })
+    }
+}
+
+type Point = (i32, i32);
+
+/// Whether segment `a`-`b`, already known to overlap the block's bounding
+/// range, touches the block whose lower-left corner is `(left, bottom)`.
+fn crosses_box(a: Point, b: Point, left: i32, bottom: i32) -> bool {
+    let corners = [
+        (left, bottom),
+        (left + BLOCK_SIZE, bottom),
+        (left, bottom + BLOCK_SIZE),
+        (left + BLOCK_SIZE, bottom + BLOCK_SIZE),
+    ];
+    let (dx, dy) = ((b.0 - a.0) as i64, (b.1 - a.1) as i64);
+    let sides = corners.map(|(x, y)| dx * (y - a.1) as i64 - dy * (x - a.0) as i64);
+    !(sides.iter().all(|&s| s > 0) || sides.iter().all(|&s| s < 0))
+}
+
+impl Map {
+    pub fn build_blockmap(
+        &mut self,
+        compression: BlockmapCompression,
+    ) -> Result<(), MapEncodeError> {
+        self.blockmap = Some(Blockmap::build(self, compression)?);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::map::testutil;
+
+    #[test]
+    fn test_build() {
+        let map = testutil::two_rooms();
+        let blockmap = Blockmap::build(&map, BlockmapCompression::None).unwrap();
+        assert_eq!((blockmap.origin_x, blockmap.origin_y), (-8, -8));
+        assert_eq!((blockmap.columns, blockmap.rows), (2, 1));
+        // The shared line at x = 64 and the bottom/top walls of the left room
+        // touch the first block; the right room's walls reach both.
+        assert_eq!(blockmap.block_at(0, 0), Some(&[0, 1, 2, 3, 4, 6][..]));
+        assert_eq!(blockmap.block_at(127, 0), Some(&[4, 5, 6][..]));
+        assert_eq!(blockmap.block_at(300, 0), None);
+    }
+
+    #[test]
+    fn test_round_trip_and_deduplication() {
+        // Interior blocks of a large room are all empty.
+        let mut sketch = testutil::Sketch::default();
+        let sector = sketch.sector();
+        sketch.polygon(&[(0, 0), (0, 1024), (1024, 1024), (1024, 0)], sector);
+        let mut map = sketch.finish();
+        map.build_blockmap(BlockmapCompression::None).unwrap();
+        let plain = map.blockmap.clone().unwrap();
+        let plain_bytes = plain.encode().unwrap();
+        assert_eq!(Blockmap::decode(&plain_bytes), Some(plain.clone()));
+
+        let deduplicated = Blockmap {
+            compression: BlockmapCompression::Deduplicate,
+            ..plain
+        };
+        let bytes = deduplicated.encode().unwrap();
+        assert!(bytes.len() < plain_bytes.len());
+        assert_eq!(Blockmap::decode(&bytes), Some(deduplicated));
+    }
+
+    #[test]
+    fn test_limits() {
+        // A header alone past the signed offset range.
+        let blockmap = Blockmap {
+            origin_x: 0,
+            origin_y: 0,
+            columns: 200,
+            rows: 200,
+            blocks: vec![vec![]; 40000],
+            compression: BlockmapCompression::None,
+        };
+        assert!(matches!(
+            blockmap.encode(),
+            Err(MapEncodeError::BlockmapTooLarge)
+        ));
+        let deduplicated = Blockmap {
+            compression: BlockmapCompression::Deduplicate,
+            ..blockmap
+        };
+        assert!(matches!(
+            deduplicated.encode(),
+            Err(MapEncodeError::BlockmapTooLarge)
+        ));
+
+        let mut map = testutil::two_rooms();
+        let crate::map::Linedefs::Doom(linedefs) = &mut map.linedefs else {
+            unreachable!()
+        };
+        let line = linedefs[0];
+        linedefs.resize(0x10000, line);
+        assert!(matches!(
+            Blockmap::build(&map, BlockmapCompression::Deduplicate),
+            Err(MapEncodeError::IndexOutOfRange {
+                lump: "BLOCKMAP",
+                index: 0xFFFF
+            })
+        ));
+    }
+}
@@ -0,0 +1,485 @@
+//! A straightforward BSP node builder producing vanilla SEGS, SSECTORS and
+//! NODES.
+//!
+//! Partitions are picked among the segs themselves, scoring each candidate by
+//! the number of segs it splits and by how unbalanced the two halves are.
+//! Split points are rounded to whole map units, as vanilla VERTEXES require.
+
+use std::collections::HashMap;
+
+use super::{BoundingBox, Map, Node, NodeChild, Seg, SubSector, Vertex};
+
+#[derive(Debug)]
+pub enum NodeBuildError {
+    NoLines,
+    BadVertex {
+        linedef: usize,
+    },
+    /// A linedef past what the 16-bit seg field can refer to.
+    TooManyLinedefs,
+}
+
+/// Distance under which a point counts as lying on a partition line.
+const EPSILON: f64 = 1.0 / 64.0;
+/// Above this many segs only a spread-out sample is scored as partitions.
+const MAX_CANDIDATES: usize = 128;
+const SPLIT_COST: usize = 8;
+/// Guards against rounding noise preventing a convex subsector forever.
+const MAX_DEPTH: usize = 256;
+
+#[derive(Debug, Clone, Copy)]
+struct WorkSeg {
+    v1: u32,
+    v2: u32,
+    p1: (f64, f64),
+    p2: (f64, f64),
+    linedef: u16,
+    direction: i16,
+    offset: f64,
+}
+impl WorkSeg {
+    fn delta(&self) -> (f64, f64) {
+        (self.p2.0 - self.p1.0, self.p2.1 - self.p1.1)
+    }
+
+    /// Signed distance of `p` from this seg's line, positive on the front
+    /// (right) side.
+    fn side_of(&self, p: (f64, f64)) -> f64 {
+        let (dx, dy) = self.delta();
+        let length = (dx * dx + dy * dy).sqrt();
+        (dy * (p.0 - self.p1.0) - dx * (p.1 - self.p1.1)) / length
+    }
+}
+
+enum Placement {
+    Front,
+    Back,
+    Split(f64, f64),
+}
+
+fn place(partition: &WorkSeg, seg: &WorkSeg) -> Placement {
+    let d1 = partition.side_of(seg.p1);
+    let d2 = partition.side_of(seg.p2);
+    if d1.abs() < EPSILON && d2.abs() < EPSILON {
+        let (pdx, pdy) = partition.delta();
+        let (sdx, sdy) = seg.delta();
+        if pdx * sdx + pdy * sdy > 0.0 {
+            Placement::Front
+        } else {
+            Placement::Back
+        }
+    } else if d1 > -EPSILON && d2 > -EPSILON {
+        Placement::Front
+    } else if d1 < EPSILON && d2 < EPSILON {
+        Placement::Back
+    } else {
+        Placement::Split(d1, d2)
+    }
+}
+
+pub(super) fn bam(dx: f64, dy: f64) -> i16 {
+    let turns = dy.atan2(dx) / std::f64::consts::TAU;
+    ((turns * 65536.0).round() as i64 as u16) as i16
+}
+
+fn bounding_box(segs: &[WorkSeg]) -> BoundingBox {
+    let points = segs.iter().flat_map(|s| [s.p1, s.p2]);
+    let (mut left, mut right, mut bottom, mut top) = (f64::MAX, f64::MIN, f64::MAX, f64::MIN);
+    for (x, y) in points {
+        left = left.min(x);
+        right = right.max(x);
+        bottom = bottom.min(y);
+        top = top.max(y);
+    }
+    BoundingBox {
+        top: top.ceil() as i16,
+        bottom: bottom.floor() as i16,
+        left: left.floor() as i16,
+        right: right.ceil() as i16,
+    }
+}
+
+struct Builder {
+    vertexes: Vec<Vertex>,
+    lookup: HashMap<(i16, i16), u32>,
+    segs: Vec<Seg>,
+    subsectors: Vec<SubSector>,
+    nodes: Vec<Node>,
+}
+impl Builder {
+    fn is_convex(segs: &[WorkSeg]) -> bool {
+        segs.iter().all(|a| {
+            segs.iter()
+                .all(|b| a.side_of(b.p1) > -EPSILON && a.side_of(b.p2) > -EPSILON)
+        })
+    }
+
+    /// Rounded split point as a vertex index, or `None` when rounding lands
+    /// on one of the seg's own endpoints.
+    fn split_vertex(&mut self, seg: &WorkSeg, d1: f64, d2: f64) -> Option<(u32, (f64, f64))> {
+        let t = d1 / (d1 - d2);
+        let (dx, dy) = seg.delta();
+        let x = (seg.p1.0 + dx * t).round();
+        let y = (seg.p1.1 + dy * t).round();
+        if (x, y) == seg.p1 || (x, y) == seg.p2 {
+            return None;
+        }
+        let key = (x as i16, y as i16);
+        let vertexes = &mut self.vertexes;
+        let index = *self.lookup.entry(key).or_insert_with(|| {
+            vertexes.push(Vertex { x: key.0, y: key.1 });
+            (vertexes.len() - 1) as u32
+        });
+        Some((index, (x, y)))
+    }
+
+    fn score(partition: &WorkSeg, segs: &[WorkSeg]) -> Option<usize> {
+        let (mut front, mut back, mut splits) = (0, 0, 0);
+        for seg in segs {
+            match place(partition, seg) {
+                Placement::Front => front += 1,
+                Placement::Back => back += 1,
+                Placement::Split(..) => splits += 1,
+            }
+        }
+        if back + splits == 0 || front + splits == 0 {
+            return None;
+        }
+        Some(splits * SPLIT_COST + (front as isize - back as isize).unsigned_abs())
+    }
+
+    fn choose_partition(segs: &[WorkSeg]) -> Option<WorkSeg> {
+        let step = segs.len().div_ceil(MAX_CANDIDATES).max(1);
+        segs.iter()
+            .step_by(step)
+            .filter_map(|candidate| Some((Builder::score(candidate, segs)?, candidate)))
+            .min_by_key(|(score, _)| *score)
+            .map(|(_, candidate)| *candidate)
+    }
+
+    fn subsector(&mut self, segs: &[WorkSeg]) -> NodeChild {
+        let first_seg = self.segs.len() as u32;
+        for seg in segs {
+            let (dx, dy) = seg.delta();
+            self.segs.push(Seg {
+                v1: seg.v1,
+                v2: seg.v2,
+                angle: bam(dx, dy),
+                linedef: seg.linedef,
+                direction: seg.direction,
+                offset: seg.offset.round() as i16,
+            });
+        }
+        self.subsectors.push(SubSector {
+            seg_count: segs.len() as u32,
+            first_seg,
+        });
+        NodeChild::SubSector((self.subsectors.len() - 1) as u32)
+    }
+
+    fn build(&mut self, segs: Vec<WorkSeg>, depth: usize) -> NodeChild {
+        if depth >= MAX_DEPTH || Builder::is_convex(&segs) {
+            return self.subsector(&segs);
+        }
+        let Some(partition) = Builder::choose_partition(&segs) else {
+            return self.subsector(&segs);
+        };
+        let (mut front, mut back) = (vec![], vec![]);
+        for seg in segs {
+            match place(&partition, &seg) {
+                Placement::Front => front.push(seg),
+                Placement::Back => back.push(seg),
+                Placement::Split(d1, d2) => match self.split_vertex(&seg, d1, d2) {
+                    Some((vertex, point)) => {
+                        let first = WorkSeg {
+                            v2: vertex,
+                            p2: point,
+                            ..seg
+                        };
+                        let (dx, dy) = (point.0 - seg.p1.0, point.1 - seg.p1.1);
+                        let second = WorkSeg {
+                            v1: vertex,
+                            p1: point,
+                            offset: seg.offset + (dx * dx + dy * dy).sqrt(),
+                            ..seg
+                        };
+                        let (front_half, back_half) = if d1 > 0.0 {
+                            (first, second)
+                        } else {
+                            (second, first)
+                        };
+                        front.push(front_half);
+                        back.push(back_half);
+                    }
+                    None if d1.abs() > d2.abs() && d1 > 0.0 => front.push(seg),
+                    None if d1.abs() <= d2.abs() && d2 > 0.0 => front.push(seg),
+                    None => back.push(seg),
+                },
+            }
+        }
+        if front.is_empty() || back.is_empty() {
+            front.append(&mut back);
+            return self.subsector(&front);
+        }
+        let right_bbox = bounding_box(&front);
+        let left_bbox = bounding_box(&back);
+        let right = self.build(front, depth + 1);
+        let left = self.build(back, depth + 1);
+        let (dx, dy) = partition.delta();
+        self.nodes.push(Node {
+            x: partition.p1.0 as i16,
+            y: partition.p1.1 as i16,
+            dx: dx as i16,
+            dy: dy as i16,
+            right_bbox,
+            left_bbox,
+            right,
+            left,
+        });
+        NodeChild::Node((self.nodes.len() - 1) as u32)
+    }
+}
+
+impl Map {
+    /// Rebuilds SEGS, SSECTORS and NODES from the linedefs. Existing vertices
+    /// are all kept, unreferenced ones included, and reused when a split lands
+    /// on them; new split vertices are appended to `vertexes`.
+    pub fn build_nodes(&mut self) -> Result<(), NodeBuildError> {
+        let lines = self.linedefs.geometry();
+        let used = lines
+            .iter()
+            .map(|l| l.v1.max(l.v2) as usize + 1)
+            .max()
+            .ok_or(NodeBuildError::NoLines)?;
+        if used > self.vertexes.len() {
+            let linedef = lines
+                .iter()
+                .position(|l| l.v1.max(l.v2) as usize >= self.vertexes.len())
+                .unwrap_or(0);
+            return Err(NodeBuildError::BadVertex { linedef });
+        }
+        let point = |v: u16| {
+            let vertex = self.vertexes[v as usize];
+            (vertex.x as f64, vertex.y as f64)
+        };
+        let mut segs = vec![];
+        for (index, line) in lines.iter().enumerate() {
+            let linedef = u16::try_from(index).map_err(|_| NodeBuildError::TooManyLinedefs)?;
+            if point(line.v1) == point(line.v2) {
+                continue;
+            }
+            let sides = [
+                (line.right, line.v1, line.v2, 0),
+                (line.left, line.v2, line.v1, 1),
+            ];
+            for (side, v1, v2, direction) in sides {
+                if side.is_some() {
+                    segs.push(WorkSeg {
+                        v1: v1 as u32,
+                        v2: v2 as u32,
+                        p1: point(v1),
+                        p2: point(v2),
+                        linedef,
+                        direction,
+                        offset: 0.0,
+                    });
+                }
+            }
+        }
+        if segs.is_empty() {
+            return Err(NodeBuildError::NoLines);
+        }
+        let lookup = self
+            .vertexes
+            .iter()
+            .enumerate()
+            .map(|(i, v)| ((v.x, v.y), i as u32))
+            .collect();
+        let mut builder = Builder {
+            vertexes: std::mem::take(&mut self.vertexes),
+            lookup,
+            segs: vec![],
+            subsectors: vec![],
+            nodes: vec![],
+        };
+        builder.build(segs, 0);
+        self.vertexes = builder.vertexes;
+        self.segs = builder.segs;
+        self.subsectors = builder.subsectors;
+        self.nodes = builder.nodes;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::map::testutil;
+
+    fn point(map: &Map, v: u32) -> (f64, f64) {
+        let vertex = map.vertexes[v as usize];
+        (vertex.x as f64, vertex.y as f64)
+    }
+
+    fn length(a: (f64, f64), b: (f64, f64)) -> f64 {
+        ((b.0 - a.0).powi(2) + (b.1 - a.1).powi(2)).sqrt()
+    }
+
+    fn contains(bbox: &BoundingBox, (x, y): (f64, f64)) -> bool {
+        x >= bbox.left as f64 - 1.0
+            && x <= bbox.right as f64 + 1.0
+            && y >= bbox.bottom as f64 - 1.0
+            && y <= bbox.top as f64 + 1.0
+    }
+
+    /// Walks the tree the way the engine does and returns the subsector
+    /// containing `(x, y)`.
+    fn locate(map: &Map, (x, y): (f64, f64)) -> usize {
+        let mut child = match map.nodes.last() {
+            Some(_) => NodeChild::Node(map.nodes.len() as u32 - 1),
+            None => NodeChild::SubSector(0),
+        };
+        loop {
+            match child {
+                NodeChild::SubSector(index) => return index as usize,
+                NodeChild::Node(index) => {
+                    let node = map.nodes[index as usize];
+                    let (dx, dy) = (x - node.x as f64, y - node.y as f64);
+                    let front = node.dy as f64 * dx - node.dx as f64 * dy > 0.0;
+                    child = if front { node.right } else { node.left };
+                }
+            }
+        }
+    }
+
+    fn subsector_sector(map: &Map, subsector: usize) -> u16 {
+        let seg = map.segs[map.subsectors[subsector].first_seg as usize];
+        let line = map.linedefs.geometry()[seg.linedef as usize];
+        let side = if seg.direction == 0 {
+            line.right
+        } else {
+            line.left
+        };
+        map.sidedefs[side.unwrap() as usize].sector
+    }
+
+    /// Checks the built tree covers every subsector once, that subsectors are
+    /// convex and inside their ancestors' boxes, and that segs cover every
+    /// linedef side exactly.
+    fn check_structure(map: &Map) {
+        let mut seen = vec![0; map.subsectors.len()];
+        let mut stack = vec![(
+            match map.nodes.len() {
+                0 => NodeChild::SubSector(0),
+                n => NodeChild::Node(n as u32 - 1),
+            },
+            vec![],
+        )];
+        while let Some((child, boxes)) = stack.pop() {
+            match child {
+                NodeChild::Node(index) => {
+                    let node = map.nodes[index as usize];
+                    let mut right = boxes.clone();
+                    right.push(node.right_bbox);
+                    let mut left = boxes;
+                    left.push(node.left_bbox);
+                    stack.push((node.right, right));
+                    stack.push((node.left, left));
+                }
+                NodeChild::SubSector(index) => {
+                    seen[index as usize] += 1;
+                    let subsector = map.subsectors[index as usize];
+                    assert!(subsector.seg_count > 0);
+                    let range = subsector.first_seg as usize
+                        ..(subsector.first_seg + subsector.seg_count) as usize;
+                    let segs = &map.segs[range];
+                    for a in segs {
+                        let (a1, a2) = (point(map, a.v1), point(map, a.v2));
+                        for b in segs {
+                            for p in [point(map, b.v1), point(map, b.v2)] {
+                                let side =
+                                    (a2.1 - a1.1) * (p.0 - a1.0) - (a2.0 - a1.0) * (p.1 - a1.1);
+                                assert!(
+                                    side / length(a1, a2) > -1.0,
+                                    "subsector {} is not convex",
+                                    index
+                                );
+                            }
+                        }
+                        for bbox in &boxes {
+                            assert!(contains(bbox, a1) && contains(bbox, a2));
+                        }
+                    }
+                }
+            }
+        }
+        assert!(
+            seen.iter().all(|&n| n == 1),
+            "subsector coverage: {:?}",
+            seen
+        );
+
+        let lines = map.linedefs.geometry();
+        let mut covered = vec![[0.0; 2]; lines.len()];
+        for seg in &map.segs {
+            covered[seg.linedef as usize][seg.direction as usize] +=
+                length(point(map, seg.v1), point(map, seg.v2));
+        }
+        for (line, covered) in lines.iter().zip(covered) {
+            let expected = length(point(map, line.v1 as u32), point(map, line.v2 as u32));
+            for (side, total) in [(line.right, covered[0]), (line.left, covered[1])] {
+                let expected = if side.is_some() { expected } else { 0.0 };
+                assert!((total - expected).abs() < 2.0, "{} vs {}", total, expected);
+            }
+        }
+        map.to_lumps().expect("built nodes must fit vanilla lumps");
+    }
+
+    #[test]
+    fn test_l_shaped_room() {
+        let mut map = testutil::l_shaped_room();
+        map.build_nodes().unwrap();
+        check_structure(&map);
+        assert_eq!(map.subsectors.len(), 2);
+        assert_eq!(map.nodes.len(), 1);
+    }
+
+    #[test]
+    fn test_two_rooms() {
+        let mut map = testutil::two_rooms();
+        map.build_nodes().unwrap();
+        check_structure(&map);
+        assert_eq!(map.subsectors.len(), 2);
+        assert_eq!(subsector_sector(&map, locate(&map, (10.0, 10.0))), 0);
+        assert_eq!(subsector_sector(&map, locate(&map, (100.0, 30.0))), 1);
+    }
+
+    #[test]
+    fn test_room_with_pillar() {
+        let mut map = testutil::room_with_pillar();
+        map.build_nodes().unwrap();
+        check_structure(&map);
+        let rebuilt_vertexes = map.vertexes.len();
+        map.build_nodes().unwrap();
+        assert_eq!(map.vertexes.len(), rebuilt_vertexes);
+        check_structure(&map);
+    }
+
+    #[test]
+    fn test_keeps_unreferenced_vertexes() {
+        let mut map = testutil::room_with_pillar();
+        map.vertexes.push(Vertex { x: 1000, y: 1000 });
+        let original = map.vertexes.clone();
+        map.build_nodes().unwrap();
+        check_structure(&map);
+        assert_eq!(map.vertexes[..original.len()], original);
+        assert!(map.vertexes.len() > original.len());
+    }
+
+    #[test]
+    fn test_no_lines() {
+        let mut map = testutil::two_rooms();
+        map.linedefs = crate::map::Linedefs::Doom(vec![]);
+        assert!(matches!(map.build_nodes(), Err(NodeBuildError::NoLines)));
+    }
+}
@@ -0,0 +1,373 @@
+//! Vanilla Doom binary map records.
+
+use crate::{
+    raw::{array_at, i16_at, u16_at},
+    LumpName,
+};
+
+/// Fixed-size little-endian record stored back to back in a map lump.
+pub(crate) trait Record: Sized {
+    const SIZE: usize;
+
+    fn decode(bytes: &[u8]) -> Self;
+
+    fn encode(&self, out: &mut Vec<u8>);
+}
+
+pub(crate) fn decode_records<T: Record>(bytes: &[u8]) -> Option<Vec<T>> {
+    if !bytes.len().is_multiple_of(T::SIZE) {
+        return None;
+    }
+    Some(bytes.chunks_exact(T::SIZE).map(T::decode).collect())
+}
+
+pub(crate) fn encode_records<T: Record>(records: &[T]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(records.len() * T::SIZE);
+    for record in records {
+        record.encode(&mut out);
+    }
+    out
+}
+
+/// Sidedef index meaning "no sidedef" in LINEDEFS.
+pub const NO_SIDEDEF: u16 = 0xFFFF;
+
+/// Linedef index of the minisegs in GL nodes, which run along no linedef.
+pub const NO_LINEDEF: u16 = 0xFFFF;
+
+pub(crate) fn sidedef(raw: u16) -> Option<u16> {
+    (raw != NO_SIDEDEF).then_some(raw)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Vertex {
+    pub x: i16,
+    pub y: i16,
+}
+impl Record for Vertex {
+    const SIZE: usize = 4;
+
+    fn decode(bytes: &[u8]) -> Vertex {
+        Vertex {
+            x: i16_at(bytes, 0),
+            y: i16_at(bytes, 2),
+        }
+    }
+
+    fn encode(&self, out: &mut Vec<u8>) {
+        out.extend(self.x.to_le_bytes());
+        out.extend(self.y.to_le_bytes());
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Thing {
+    pub x: i16,
+    pub y: i16,
+    pub angle: i16,
+    pub kind: i16,
+    pub flags: i16,
+}
+impl Record for Thing {
+    const SIZE: usize = 10;
+
+    fn decode(bytes: &[u8]) -> Thing {
+        Thing {
+            x: i16_at(bytes, 0),
+            y: i16_at(bytes, 2),
+            angle: i16_at(bytes, 4),
+            kind: i16_at(bytes, 6),
+            flags: i16_at(bytes, 8),
+        }
+    }
+
+    fn encode(&self, out: &mut Vec<u8>) {
+        out.extend(self.x.to_le_bytes());
+        out.extend(self.y.to_le_bytes());
+        out.extend(self.angle.to_le_bytes());
+        out.extend(self.kind.to_le_bytes());
+        out.extend(self.flags.to_le_bytes());
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Linedef {
+    pub v1: u16,
+    pub v2: u16,
+    pub flags: i16,
+    pub special: i16,
+    pub tag: i16,
+    pub right: Option<u16>,
+    pub left: Option<u16>,
+}
+impl Record for Linedef {
+    const SIZE: usize = 14;
+
+    fn decode(bytes: &[u8]) -> Linedef {
+        Linedef {
+            v1: u16_at(bytes, 0),
+            v2: u16_at(bytes, 2),
+            flags: i16_at(bytes, 4),
+            special: i16_at(bytes, 6),
+            tag: i16_at(bytes, 8),
+            right: sidedef(u16_at(bytes, 10)),
+            left: sidedef(u16_at(bytes, 12)),
+        }
+    }
+
+    fn encode(&self, out: &mut Vec<u8>) {
+        out.extend(self.v1.to_le_bytes());
+        out.extend(self.v2.to_le_bytes());
+        out.extend(self.flags.to_le_bytes());
+        out.extend(self.special.to_le_bytes());
+        out.extend(self.tag.to_le_bytes());
+        out.extend(self.right.unwrap_or(NO_SIDEDEF).to_le_bytes());
+        out.extend(self.left.unwrap_or(NO_SIDEDEF).to_le_bytes());
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Sidedef {
+    pub x_offset: i16,
+    pub y_offset: i16,
+    pub upper: LumpName,
+    pub lower: LumpName,
+    pub middle: LumpName,
+    pub sector: u16,
+}
+impl Record for Sidedef {
+    const SIZE: usize = 30;
+
+    fn decode(bytes: &[u8]) -> Sidedef {
+        Sidedef {
+            x_offset: i16_at(bytes, 0),
+            y_offset: i16_at(bytes, 2),
+            upper: LumpName::from_short_bytes(array_at(bytes, 4)),
+            lower: LumpName::from_short_bytes(array_at(bytes, 12)),
+            middle: LumpName::from_short_bytes(array_at(bytes, 20)),
+            sector: u16_at(bytes, 28),
+        }
+    }
+
+    fn encode(&self, out: &mut Vec<u8>) {
+        out.extend(self.x_offset.to_le_bytes());
+        out.extend(self.y_offset.to_le_bytes());
+        out.extend(&self.upper.0[..8]);
+        out.extend(&self.lower.0[..8]);
+        out.extend(&self.middle.0[..8]);
+        out.extend(self.sector.to_le_bytes());
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Sector {
+    pub floor_height: i16,
+    pub ceiling_height: i16,
+    pub floor_texture: LumpName,
+    pub ceiling_texture: LumpName,
+    pub light: i16,
+    pub special: i16,
+    pub tag: i16,
+}
+impl Record for Sector {
+    const SIZE: usize = 26;
+
+    fn decode(bytes: &[u8]) -> Sector {
+        Sector {
+            floor_height: i16_at(bytes, 0),
+            ceiling_height: i16_at(bytes, 2),
+            floor_texture: LumpName::from_short_bytes(array_at(bytes, 4)),
+            ceiling_texture: LumpName::from_short_bytes(array_at(bytes, 12)),
+            light: i16_at(bytes, 20),
+            special: i16_at(bytes, 22),
+            tag: i16_at(bytes, 24),
+        }
+    }
+
+    fn encode(&self, out: &mut Vec<u8>) {
+        out.extend(self.floor_height.to_le_bytes());
+        out.extend(self.ceiling_height.to_le_bytes());
+        out.extend(&self.floor_texture.0[..8]);
+        out.extend(&self.ceiling_texture.0[..8]);
+        out.extend(self.light.to_le_bytes());
+        out.extend(self.special.to_le_bytes());
+        out.extend(self.tag.to_le_bytes());
+    }
+}
+
+/// Vertex and seg indices are 32 bits wide so extended node formats fit the
+/// same model; vanilla lumps can only store 16 bits of them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Seg {
+    pub v1: u32,
+    pub v2: u32,
+    pub angle: i16,
+    /// `NO_LINEDEF` for minisegs, which vanilla SEGS cannot store.
+    pub linedef: u16,
+    /// 0 when the seg runs along the linedef's right side, 1 for the left.
+    pub direction: i16,
+    pub offset: i16,
+}
+impl Record for Seg {
+    const SIZE: usize = 12;
+
+    fn decode(bytes: &[u8]) -> Seg {
+        Seg {
+            v1: u16_at(bytes, 0) as u32,
+            v2: u16_at(bytes, 2) as u32,
+            angle: i16_at(bytes, 4),
+            linedef: u16_at(bytes, 6),
+            direction: i16_at(bytes, 8),
+            offset: i16_at(bytes, 10),
+        }
+    }
+
+    fn encode(&self, out: &mut Vec<u8>) {
+        out.extend((self.v1 as u16).to_le_bytes());
+        out.extend((self.v2 as u16).to_le_bytes());
+        out.extend(self.angle.to_le_bytes());
+        out.extend(self.linedef.to_le_bytes());
+        out.extend(self.direction.to_le_bytes());
+        out.extend(self.offset.to_le_bytes());
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SubSector {
+    pub seg_count: u32,
+    pub first_seg: u32,
+}
+impl Record for SubSector {
+    const SIZE: usize = 4;
+
+    fn decode(bytes: &[u8]) -> SubSector {
+        SubSector {
+            seg_count: u16_at(bytes, 0) as u32,
+            first_seg: u16_at(bytes, 2) as u32,
+        }
+    }
+
+    fn encode(&self, out: &mut Vec<u8>) {
+        out.extend((self.seg_count as u16).to_le_bytes());
+        out.extend((self.first_seg as u16).to_le_bytes());
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BoundingBox {
+    pub top: i16,
+    pub bottom: i16,
+    pub left: i16,
+    pub right: i16,
+}
+impl BoundingBox {
+    fn decode(bytes: &[u8]) -> BoundingBox {
+        BoundingBox {
+            top: i16_at(bytes, 0),
+            bottom: i16_at(bytes, 2),
+            left: i16_at(bytes, 4),
+            right: i16_at(bytes, 6),
+        }
+    }
+
+    fn encode(&self, out: &mut Vec<u8>) {
+        out.extend(self.top.to_le_bytes());
+        out.extend(self.bottom.to_le_bytes());
+        out.extend(self.left.to_le_bytes());
+        out.extend(self.right.to_le_bytes());
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NodeChild {
+    Node(u32),
+    SubSector(u32),
+}
+impl NodeChild {
+    fn from_vanilla(raw: u16) -> NodeChild {
+        if raw & 0x8000 != 0 {
+            NodeChild::SubSector((raw & 0x7FFF) as u32)
+        } else {
+            NodeChild::Node(raw as u32)
+        }
+    }
+
+    fn to_vanilla(self) -> u16 {
+        match self {
+            NodeChild::Node(index) => index as u16,
+            NodeChild::SubSector(index) => index as u16 | 0x8000,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Node {
+    pub x: i16,
+    pub y: i16,
+    pub dx: i16,
+    pub dy: i16,
+    pub right_bbox: BoundingBox,
+    pub left_bbox: BoundingBox,
+    pub right: NodeChild,
+    pub left: NodeChild,
+}
+impl Record for Node {
+    const SIZE: usize = 28;
+
+    fn decode(bytes: &[u8]) -> Node {
+        Node {
+            x: i16_at(bytes, 0),
+            y: i16_at(bytes, 2),
+            dx: i16_at(bytes, 4),
+            dy: i16_at(bytes, 6),
+            right_bbox: BoundingBox::decode(&bytes[8..16]),
+            left_bbox: BoundingBox::decode(&bytes[16..24]),
+            right: NodeChild::from_vanilla(u16_at(bytes, 24)),
+            left: NodeChild::from_vanilla(u16_at(bytes, 26)),
+        }
+    }
+
+    fn encode(&self, out: &mut Vec<u8>) {
+        out.extend(self.x.to_le_bytes());
+        out.extend(self.y.to_le_bytes());
+        out.extend(self.dx.to_le_bytes());
+        out.extend(self.dy.to_le_bytes());
+        self.right_bbox.encode(out);
+        self.left_bbox.encode(out);
+        out.extend(self.right.to_vanilla().to_le_bytes());
+        out.extend(self.left.to_vanilla().to_le_bytes());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode_records() {
+        let bytes = [0x40, 0, 0xC0, 0xFF, 0x10, 0, 0x20, 0];
+        let vertexes: Vec<Vertex> = decode_records(&bytes).unwrap();
+        assert_eq!(
+            vertexes,
+            [Vertex { x: 64, y: -64 }, Vertex { x: 16, y: 32 }]
+        );
+        assert!(decode_records::<Vertex>(&bytes[..6]).is_none());
+    }
+
+    #[test]
+    fn test_linedef_without_left_side() {
+        let bytes = [0, 0, 1, 0, 1, 0, 0, 0, 0, 0, 5, 0, 0xFF, 0xFF];
+        let linedef = Linedef::decode(&bytes);
+        assert_eq!(linedef.right, Some(5));
+        assert_eq!(linedef.left, None);
+    }
+
+    #[test]
+    fn test_node_children() {
+        let mut bytes = vec![0; 24];
+        bytes.extend([0x03, 0x80, 0x07, 0x00]);
+        let node = Node::decode(&bytes);
+        assert_eq!(node.right, NodeChild::SubSector(3));
+        assert_eq!(node.left, NodeChild::Node(7));
+    }
+}
@@ -0,0 +1,803 @@
+//! ZDoom extended nodes (`XNOD`, `ZNOD` and their GL variants) and glBSP
+//! `GL_*` lumps, decoded into the vanilla seg, subsector and node records.
+
+use std::{io::Read, ops::Range};
+
+use flate2::read::ZlibDecoder;
+
+use crate::raw::{array_at, i16_at, i32_at, u16_at, u8_at};
+use crate::{LumpName, Wad};
+
+use super::{
+    bsp::bam,
+    doom::{decode_records, NO_LINEDEF},
+    BoundingBox, Map, MapDecodeError, MapFormat, MapLumps, Node, NodeChild, Seg, SubSector, Vertex,
+};
+
+#[derive(Debug)]
+pub enum NodeDecodeError {
+    UnknownSignature([u8; 4]),
+    Truncated,
+    FailedToInflate(std::io::Error),
+    /// A linedef index that does not fit the 16-bit seg field.
+    LinedefOutOfRange(u32),
+    VertexOutOfRange(u32),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NodeFormat {
+    Xnod,
+    Znod,
+    Xgln,
+    Zgln,
+    Xgl2,
+    Zgl2,
+    Xgl3,
+    Zgl3,
+    /// glBSP `GL_*` lumps of the given version.
+    Gl(u8),
+}
+impl NodeFormat {
+    pub fn from_signature(signature: &[u8; 4]) -> Option<NodeFormat> {
+        Some(match signature {
+            b"XNOD" => NodeFormat::Xnod,
+            b"ZNOD" => NodeFormat::Znod,
+            b"XGLN" => NodeFormat::Xgln,
+            b"ZGLN" => NodeFormat::Zgln,
+            b"XGL2" => NodeFormat::Xgl2,
+            b"ZGL2" => NodeFormat::Zgl2,
+            b"XGL3" => NodeFormat::Xgl3,
+            b"ZGL3" => NodeFormat::Zgl3,
+            _ => return None,
+        })
+    }
+
+    pub fn is_compressed(self) -> bool {
+        matches!(
+            self,
+            NodeFormat::Znod | NodeFormat::Zgln | NodeFormat::Zgl2 | NodeFormat::Zgl3
+        )
+    }
+
+    /// GL nodes store one vertex per seg and close every subsector with
+    /// minisegs along the partition lines.
+    pub fn is_gl(self) -> bool {
+        !matches!(self, NodeFormat::Xnod | NodeFormat::Znod)
+    }
+}
+
+/// A vertex in 16.16 fixed point.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FixedVertex {
+    pub x: i32,
+    pub y: i32,
+}
+impl FixedVertex {
+    fn rounded(self) -> Vertex {
+        let round = |v: i32| ((v as i64 + 0x8000) >> 16) as i16;
+        Vertex {
+            x: round(self.x),
+            y: round(self.y),
+        }
+    }
+}
+
+/// Seg vertex indices below `original_vertices` refer to the map's
+/// `VERTEXES`; the rest index `extra_vertices`. Seg angles and offsets are
+/// not stored by these formats and are left at zero until the nodes are
+/// applied to a map.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExtendedNodes {
+    pub format: NodeFormat,
+    pub original_vertices: u32,
+    pub extra_vertices: Vec<FixedVertex>,
+    pub segs: Vec<Seg>,
+    pub subsectors: Vec<SubSector>,
+    pub nodes: Vec<Node>,
+}
+
+/// A bounds-checked little-endian cursor.
+struct Cursor<'a> {
+    bytes: &'a [u8],
+    at: usize,
+}
+impl<'a> Cursor<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], NodeDecodeError> {
+        let end = self.at.checked_add(len).ok_or(NodeDecodeError::Truncated)?;
+        let slice = self
+            .bytes
+            .get(self.at..end)
+            .ok_or(NodeDecodeError::Truncated)?;
+        self.at = end;
+        Ok(slice)
+    }
+
+    fn u8(&mut self) -> Result<u8, NodeDecodeError> {
+        Ok(u8_at(self.take(1)?, 0))
+    }
+
+    fn i16(&mut self) -> Result<i16, NodeDecodeError> {
+        Ok(i16_at(self.take(2)?, 0))
+    }
+
+    fn u16(&mut self) -> Result<u16, NodeDecodeError> {
+        Ok(u16_at(self.take(2)?, 0))
+    }
+
+    fn i32(&mut self) -> Result<i32, NodeDecodeError> {
+        Ok(i32_at(self.take(4)?, 0))
+    }
+
+    fn u32(&mut self) -> Result<u32, NodeDecodeError> {
+        Ok(self.i32()? as u32)
+    }
+
+    /// Reads a count and checks that `count * record` bytes remain, so a
+    /// corrupt count cannot trigger a huge allocation.
+    fn count(&mut self, record: usize) -> Result<usize, NodeDecodeError> {
+        let count = self.u32()? as usize;
+        match count.checked_mul(record) {
+            Some(len) if len <= self.bytes.len() - self.at => Ok(count),
+            _ => Err(NodeDecodeError::Truncated),
+        }
+    }
+
+    fn bbox(&mut self) -> Result<BoundingBox, NodeDecodeError> {
+        Ok(BoundingBox {
+            top: self.i16()?,
+            bottom: self.i16()?,
+            left: self.i16()?,
+            right: self.i16()?,
+        })
+    }
+}
+
+fn child(raw: u32) -> NodeChild {
+    if raw & 0x8000_0000 != 0 {
+        NodeChild::SubSector(raw & 0x7FFF_FFFF)
+    } else {
+        NodeChild::Node(raw)
+    }
+}
+
+fn linedef(raw: u32, none: u32) -> Result<u16, NodeDecodeError> {
+    match raw {
+        raw if raw == none => Ok(NO_LINEDEF),
+        raw if raw < 0xFFFF => Ok(raw as u16),
+        raw => Err(NodeDecodeError::LinedefOutOfRange(raw)),
+    }
+}
+
+/// Fills in each GL seg's end vertex with the start of the next seg around
+/// its subsector.
+fn close_gl_segs(segs: &mut [Seg], subsectors: &[SubSector]) -> Result<(), NodeDecodeError> {
+    for subsector in subsectors {
+        let first = subsector.first_seg as usize;
+        let range = segs
+            .get_mut(first..first + subsector.seg_count as usize)
+            .ok_or(NodeDecodeError::Truncated)?;
+        for i in 0..range.len() {
+            range[i].v2 = range[(i + 1) % range.len()].v1;
+        }
+    }
+    Ok(())
+}
+
+impl ExtendedNodes {
+    /// Decodes a ZDoom node lump, as found in `NODES`, `SSECTORS` or
+    /// `ZNODES`.
+    pub fn decode(bytes: &[u8]) -> Result<ExtendedNodes, NodeDecodeError> {
+        if bytes.len() < 4 {
+            return Err(NodeDecodeError::Truncated);
+        }
+        let signature: [u8; 4] = array_at(bytes, 0);
+        let format = NodeFormat::from_signature(&signature)
+            .ok_or(NodeDecodeError::UnknownSignature(signature))?;
+        let inflated;
+        let body = if format.is_compressed() {
+            let mut data = vec![];
+            ZlibDecoder::new(&bytes[4..])
+                .read_to_end(&mut data)
+                .map_err(NodeDecodeError::FailedToInflate)?;
+            inflated = data;
+            &inflated[..]
+        } else {
+            &bytes[4..]
+        };
+        let mut cursor = Cursor { bytes: body, at: 0 };
+
+        let original_vertices = cursor.u32()?;
+        let count = cursor.count(8)?;
+        let mut extra_vertices = Vec::with_capacity(count);
+        for _ in 0..count {
+            extra_vertices.push(FixedVertex {
+                x: cursor.i32()?,
+                y: cursor.i32()?,
+            });
+        }
+
+        let count = cursor.count(4)?;
+        let mut subsectors = Vec::with_capacity(count);
+        let mut first_seg = 0u32;
+        for _ in 0..count {
+            let seg_count = cursor.u32()?;
+            subsectors.push(SubSector {
+                seg_count,
+                first_seg,
+            });
+            first_seg = first_seg.wrapping_add(seg_count);
+        }
+
+        let wide_lines = matches!(
+            format,
+            NodeFormat::Xgl2 | NodeFormat::Zgl2 | NodeFormat::Xgl3 | NodeFormat::Zgl3
+        );
+        let count = cursor.count(if wide_lines { 13 } else { 11 })?;
+        let mut segs = Vec::with_capacity(count);
+        for _ in 0..count {
+            let v1 = cursor.u32()?;
+            // GL segs store their partner seg here instead of an end vertex.
+            let v2 = cursor.u32()?;
+            let linedef = match wide_lines {
+                true => linedef(cursor.u32()?, 0xFFFF_FFFF)?,
+                false => linedef(cursor.u16()? as u32, 0xFFFF)?,
+            };
+            segs.push(Seg {
+                v1,
+                v2: if format.is_gl() { v1 } else { v2 },
+                angle: 0,
+                linedef,
+                direction: cursor.u8()? as i16,
+                offset: 0,
+            });
+        }
+        if format.is_gl() {
+            close_gl_segs(&mut segs, &subsectors)?;
+        }
+
+        let fixed_partitions = matches!(format, NodeFormat::Xgl3 | NodeFormat::Zgl3);
+        let count = cursor.count(if fixed_partitions { 40 } else { 32 })?;
+        let mut nodes = Vec::with_capacity(count);
+        for _ in 0..count {
+            let [x, y, dx, dy] = if fixed_partitions {
+                let mut whole = || cursor.i32().map(|v| (v >> 16) as i16);
+                [whole()?, whole()?, whole()?, whole()?]
+            } else {
+                [cursor.i16()?, cursor.i16()?, cursor.i16()?, cursor.i16()?]
+            };
+            nodes.push(Node {
+                x,
+                y,
+                dx,
+                dy,
+                right_bbox: cursor.bbox()?,
+                left_bbox: cursor.bbox()?,
+                right: child(cursor.u32()?),
+                left: child(cursor.u32()?),
+            });
+        }
+
+        Ok(ExtendedNodes {
+            format,
+            original_vertices,
+            extra_vertices,
+            segs,
+            subsectors,
+            nodes,
+        })
+    }
+
+    /// Decodes glBSP `GL_VERT`, `GL_SEGS`, `GL_SSECT` and `GL_NODES` lumps
+    /// of versions 1 to 5. GL vertices are numbered after the map's
+    /// `map_vertices` regular ones.
+    pub fn from_gl_lumps(
+        vert: &[u8],
+        segs: &[u8],
+        ssect: &[u8],
+        nodes: &[u8],
+        map_vertices: u32,
+    ) -> Result<ExtendedNodes, NodeDecodeError> {
+        let mut version = match vert.get(..4) {
+            Some(b"gNd2") | Some(b"gNd3") => 2,
+            Some(b"gNd4") => 4,
+            Some(b"gNd5") => 5,
+            _ => 1,
+        };
+        if segs.starts_with(b"gNd3") {
+            version = 3;
+        }
+
+        let mut cursor = Cursor {
+            bytes: vert,
+            at: if version == 1 { 0 } else { 4 },
+        };
+        let mut extra_vertices = vec![];
+        while cursor.at < vert.len() {
+            extra_vertices.push(match version {
+                1 => FixedVertex {
+                    x: (cursor.i16()? as i32) << 16,
+                    y: (cursor.i16()? as i32) << 16,
+                },
+                _ => FixedVertex {
+                    x: cursor.i32()?,
+                    y: cursor.i32()?,
+                },
+            });
+        }
+
+        let (gl_flag, wide) = match version {
+            1 | 2 => (0x8000, false),
+            3 => (0x4000_0000, true),
+            _ => (0x8000_0000, true),
+        };
+        let vertex = |raw: u32| match raw & gl_flag {
+            0 => raw,
+            _ => map_vertices + (raw & !gl_flag),
+        };
+        let mut cursor = Cursor {
+            bytes: segs,
+            at: if version == 3 { 4 } else { 0 },
+        };
+        let mut gl_segs = vec![];
+        while cursor.at < segs.len() {
+            let (v1, v2) = match wide {
+                true => (cursor.u32()?, cursor.u32()?),
+                false => (cursor.u16()? as u32, cursor.u16()? as u32),
+            };
+            let linedef = cursor.u16()?;
+            let direction = cursor.i16()?;
+            // The partner seg is not part of the model.
+            cursor.take(if wide { 4 } else { 2 })?;
+            gl_segs.push(Seg {
+                v1: vertex(v1),
+                v2: vertex(v2),
+                angle: 0,
+                linedef,
+                direction,
+                offset: 0,
+            });
+        }
+
+        let mut cursor = Cursor {
+            bytes: ssect,
+            at: if ssect.starts_with(b"gNd3") { 4 } else { 0 },
+        };
+        let mut subsectors = vec![];
+        while cursor.at < ssect.len() {
+            subsectors.push(match version >= 3 {
+                true => SubSector {
+                    seg_count: cursor.u32()?,
+                    first_seg: cursor.u32()?,
+                },
+                false => SubSector {
+                    seg_count: cursor.u16()? as u32,
+                    first_seg: cursor.u16()? as u32,
+                },
+            });
+        }
+
+        let nodes = if version >= 4 {
+            let mut cursor = Cursor {
+                bytes: nodes,
+                at: 0,
+            };
+            let mut decoded = vec![];
+            while cursor.at < nodes.len() {
+                decoded.push(Node {
+                    x: cursor.i16()?,
+                    y: cursor.i16()?,
+                    dx: cursor.i16()?,
+                    dy: cursor.i16()?,
+                    right_bbox: cursor.bbox()?,
+                    left_bbox: cursor.bbox()?,
+                    right: child(cursor.u32()?),
+                    left: child(cursor.u32()?),
+                });
+            }
+            decoded
+        } else {
+            decode_records(nodes).ok_or(NodeDecodeError::Truncated)?
+        };
+
+        Ok(ExtendedNodes {
+            format: NodeFormat::Gl(version),
+            original_vertices: map_vertices,
+            extra_vertices,
+            segs: gl_segs,
+            subsectors,
+            nodes,
+        })
+    }
+
+    /// Finds and decodes the extended or GL nodes of the named map: ZDoom
+    /// nodes in `ZNODES`, `NODES` or `SSECTORS`, then glBSP lumps under the
+    /// `GL_` marker that follows the map.
+    pub fn from_wad(wad: &Wad, map: &str) -> Result<Option<ExtendedNodes>, MapDecodeError> {
+        let lumps = wad
+            .find_map(map)
+            .ok_or_else(|| MapDecodeError::NoSuchMap(map.to_string()))?;
+        if let Some(nodes) = ExtendedNodes::from_node_lumps(wad, &lumps)? {
+            return Ok(Some(nodes));
+        }
+        let vertexes = match lumps.vertexes {
+            Some(index) => {
+                wad.read_lump(index)
+                    .map_err(MapDecodeError::FailedToReadLump)?
+                    .len()
+                    / 4
+            }
+            None => 0,
+        };
+        ExtendedNodes::from_gl_group(wad, &lumps, vertexes as u32)
+    }
+
+    /// ZDoom nodes, recognised by their signature, in the map's own lumps.
+    pub(super) fn from_node_lumps(
+        wad: &Wad,
+        lumps: &MapLumps,
+    ) -> Result<Option<ExtendedNodes>, MapDecodeError> {
+        for index in [lumps.znodes, lumps.nodes, lumps.ssectors]
+            .into_iter()
+            .flatten()
+        {
+            let data = wad
+                .read_lump(index)
+                .map_err(MapDecodeError::FailedToReadLump)?;
+            if data.len() >= 4 && NodeFormat::from_signature(&array_at(&data, 0)).is_some() {
+                return ExtendedNodes::decode(&data)
+                    .map(Some)
+                    .map_err(MapDecodeError::Nodes);
+            }
+        }
+        Ok(None)
+    }
+
+    /// glBSP nodes in the map's `GL_` group, whose vertices are numbered
+    /// after the map's `map_vertices` decoded ones.
+    pub(super) fn from_gl_group(
+        wad: &Wad,
+        lumps: &MapLumps,
+        map_vertices: u32,
+    ) -> Result<Option<ExtendedNodes>, MapDecodeError> {
+        if lumps.format == MapFormat::Udmf {
+            return Ok(None);
+        }
+        let mut gl: [Option<Vec<u8>>; 4] = Default::default();
+        for index in gl_group(wad, lumps) {
+            let slot = match gl_component(&wad.directory[index].name) {
+                Some("VERT") => 0,
+                Some("SEGS") => 1,
+                Some("SSECT") => 2,
+                Some("NODES") => 3,
+                _ => continue,
+            };
+            gl[slot] = Some(
+                wad.read_lump(index)
+                    .map_err(MapDecodeError::FailedToReadLump)?,
+            );
+        }
+        let [Some(vert), Some(segs), Some(ssect), Some(nodes)] = gl else {
+            return Ok(None);
+        };
+        ExtendedNodes::from_gl_lumps(&vert, &segs, &ssect, &nodes, map_vertices)
+            .map(Some)
+            .map_err(MapDecodeError::Nodes)
+    }
+}
+
+/// What follows `GL_` in a glBSP lump name.
+fn gl_component(name: &LumpName) -> Option<&'static str> {
+    let name = name.to_string().to_ascii_uppercase();
+    ["VERT", "SEGS", "SSECT", "NODES", "PVS"]
+        .into_iter()
+        .find(|component| name.strip_prefix("GL_") == Some(component))
+}
+
+/// The glBSP lumps that follow the map: its `GL_` marker, named after the
+/// map or `GL_LEVEL` when that would not fit, then `GL_VERT`, `GL_SEGS`,
+/// `GL_SSECT`, `GL_NODES` and `GL_PVS` in any order.
+pub(super) fn gl_group(wad: &Wad, lumps: &MapLumps) -> Range<usize> {
+    let start = lumps.range.end;
+    let markers = [format!("GL_{}", lumps.name), "GL_LEVEL".to_string()];
+    let mut end = start;
+    while let Some(entry) = wad.directory.get(end) {
+        let name = entry.name.to_string();
+        let marker = end == start && markers.iter().any(|m| m.eq_ignore_ascii_case(&name));
+        if gl_component(&entry.name).is_none() && !marker {
+            break;
+        }
+        end += 1;
+    }
+    start..end
+}
+
+impl Map {
+    /// Replaces the map's nodes with decoded extended ones. Extra vertices
+    /// are rounded to whole units and appended to `vertexes`, and seg angles
+    /// and offsets are derived from the full-precision positions.
+    pub fn apply_nodes(&mut self, nodes: ExtendedNodes) -> Result<(), NodeDecodeError> {
+        let original = nodes.original_vertices as usize;
+        if self.vertexes.len() < original {
+            return Err(NodeDecodeError::VertexOutOfRange(nodes.original_vertices));
+        }
+        self.vertexes.truncate(original);
+        let mut points: Vec<(f64, f64)> = self
+            .vertexes
+            .iter()
+            .map(|v| (v.x as f64, v.y as f64))
+            .collect();
+        for vertex in &nodes.extra_vertices {
+            self.vertexes.push(vertex.rounded());
+            points.push((vertex.x as f64 / 65536.0, vertex.y as f64 / 65536.0));
+        }
+
+        let lines = self.linedefs.geometry();
+        let mut segs = nodes.segs;
+        for seg in &mut segs {
+            let point = |v: u32| {
+                points
+                    .get(v as usize)
+                    .copied()
+                    .ok_or(NodeDecodeError::VertexOutOfRange(v))
+            };
+            let (a, b) = (point(seg.v1)?, point(seg.v2)?);
+            seg.angle = bam(b.0 - a.0, b.1 - a.1);
+            if let Some(line) = lines.get(seg.linedef as usize) {
+                let start = if seg.direction == 0 { line.v1 } else { line.v2 };
+                if let Ok(start) = point(start as u32) {
+                    seg.offset = (a.0 - start.0).hypot(a.1 - start.1).round() as i16;
+                }
+            }
+        }
+        self.segs = segs;
+        self.subsectors = nodes.subsectors;
+        self.nodes = nodes.nodes;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use flate2::{write::ZlibEncoder, Compression};
+
+    use super::*;
+    use crate::map::testutil;
+    use crate::testutil::decode;
+    use crate::{Lump, LumpName, WadBuilder};
+
+    fn raw_child(child: NodeChild) -> u32 {
+        match child {
+            NodeChild::Node(index) => index,
+            NodeChild::SubSector(index) => index | 0x8000_0000,
+        }
+    }
+
+    fn bbox_bytes(bbox: &BoundingBox, out: &mut Vec<u8>) {
+        for v in [bbox.top, bbox.bottom, bbox.left, bbox.right] {
+            out.extend(v.to_le_bytes());
+        }
+    }
+
+    /// Writes `map`'s nodes as an `XNOD` body with the split vertices moved
+    /// into the extra vertex list.
+    fn xnod_body(map: &Map, original: u32) -> Vec<u8> {
+        let mut out = vec![];
+        out.extend(original.to_le_bytes());
+        let extra = &map.vertexes[original as usize..];
+        out.extend((extra.len() as u32).to_le_bytes());
+        for v in extra {
+            out.extend(((v.x as i32) << 16).to_le_bytes());
+            out.extend(((v.y as i32) << 16).to_le_bytes());
+        }
+        out.extend((map.subsectors.len() as u32).to_le_bytes());
+        for subsector in &map.subsectors {
+            out.extend(subsector.seg_count.to_le_bytes());
+        }
+        out.extend((map.segs.len() as u32).to_le_bytes());
+        for seg in &map.segs {
+            out.extend(seg.v1.to_le_bytes());
+            out.extend(seg.v2.to_le_bytes());
+            out.extend(seg.linedef.to_le_bytes());
+            out.push(seg.direction as u8);
+        }
+        out.extend((map.nodes.len() as u32).to_le_bytes());
+        for node in &map.nodes {
+            for v in [node.x, node.y, node.dx, node.dy] {
+                out.extend(v.to_le_bytes());
+            }
+            bbox_bytes(&node.right_bbox, &mut out);
+            bbox_bytes(&node.left_bbox, &mut out);
+            out.extend(raw_child(node.right).to_le_bytes());
+            out.extend(raw_child(node.left).to_le_bytes());
+        }
+        out
+    }
+
+    fn built() -> (Map, u32) {
+        let mut map = testutil::room_with_pillar();
+        let original = map.vertexes.len() as u32;
+        map.build_nodes().unwrap();
+        (map, original)
+    }
+
+    #[test]
+    fn test_xnod_and_znod() {
+        let (map, original) = built();
+        let body = xnod_body(&map, original);
+        let mut compressed = b"ZNOD".to_vec();
+        let mut encoder = ZlibEncoder::new(vec![], Compression::default());
+        encoder.write_all(&body).unwrap();
+        compressed.extend(encoder.finish().unwrap());
+
+        for lump in [[&b"XNOD"[..], &body].concat(), compressed] {
+            let nodes = ExtendedNodes::decode(&lump).unwrap();
+            assert_eq!(nodes.original_vertices, original);
+            assert_eq!(nodes.subsectors, map.subsectors);
+            assert_eq!(nodes.nodes, map.nodes);
+
+            let mut decoded = map.clone();
+            decoded.segs.clear();
+            decoded.apply_nodes(nodes).unwrap();
+            assert_eq!(decoded.vertexes, map.vertexes);
+            assert_eq!(decoded.segs, map.segs);
+        }
+        assert!(matches!(
+            ExtendedNodes::decode(b"XNOD\0\0"),
+            Err(NodeDecodeError::Truncated)
+        ));
+        assert!(matches!(
+            ExtendedNodes::decode(b"ZNOD\0\0\0\0"),
+            Err(NodeDecodeError::FailedToInflate(_))
+        ));
+    }
+
+    #[test]
+    fn test_map_from_wad_reads_extended_nodes() {
+        let (map, original) = built();
+        let mut lumps = map.to_lumps().unwrap();
+        let vertexes = &mut lumps[4];
+        vertexes.data.truncate(original as usize * 4);
+        lumps[5].data.clear();
+        lumps[6].data.clear();
+        lumps[7].data = [&b"XNOD"[..], &xnod_body(&map, original)].concat();
+        let mut builder = WadBuilder::pwad();
+        builder.lumps.extend(lumps);
+        let wad = decode(&builder);
+        let decoded = Map::from_wad(&wad, "MAP01").unwrap();
+        assert_eq!(decoded.vertexes, map.vertexes);
+        assert_eq!(decoded.segs, map.segs);
+        assert_eq!(decoded.nodes, map.nodes);
+        let nodes = ExtendedNodes::from_wad(&wad, "MAP01").unwrap().unwrap();
+        assert_eq!(nodes.format, NodeFormat::Xnod);
+    }
+
+    #[test]
+    fn test_map_from_wad_reads_gl_nodes_in_ssectors() {
+        let (map, original) = built();
+        let mut zgln = b"ZGLN".to_vec();
+        let mut encoder = ZlibEncoder::new(vec![], Compression::default());
+        encoder.write_all(&xnod_body(&map, original)).unwrap();
+        zgln.extend(encoder.finish().unwrap());
+        let mut lumps = map.to_lumps().unwrap();
+        lumps[4].data.truncate(original as usize * 4);
+        lumps[5].data.clear();
+        lumps[6].data = zgln;
+        lumps[7].data.clear();
+        let mut builder = WadBuilder::pwad();
+        builder.lumps.extend(lumps);
+        let wad = decode(&builder);
+        let decoded = Map::from_wad(&wad, "MAP01").unwrap();
+        assert_eq!(decoded.vertexes, map.vertexes);
+        assert_eq!(decoded.subsectors, map.subsectors);
+        assert_eq!(decoded.nodes, map.nodes);
+        let starts =
+            |map: &Map| -> Vec<(u32, u16)> { map.segs.iter().map(|s| (s.v1, s.linedef)).collect() };
+        assert_eq!(starts(&decoded), starts(&map));
+    }
+
+    /// A glBSP version 5 `GL_` group holding `map`'s nodes, with the split
+    /// vertices as GL vertices.
+    fn gl_lumps(map: &Map, original: u32) -> Vec<Lump> {
+        let gl = |v: u32| match v >= original {
+            true => (v - original) | 0x8000_0000,
+            false => v,
+        };
+        let mut vert = b"gNd5".to_vec();
+        for v in &map.vertexes[original as usize..] {
+            vert.extend(((v.x as i32) << 16).to_le_bytes());
+            vert.extend(((v.y as i32) << 16).to_le_bytes());
+        }
+        let mut segs = vec![];
+        for seg in &map.segs {
+            segs.extend(gl(seg.v1).to_le_bytes());
+            segs.extend(gl(seg.v2).to_le_bytes());
+            segs.extend(seg.linedef.to_le_bytes());
+            segs.extend(seg.direction.to_le_bytes());
+            segs.extend(0xFFFF_FFFFu32.to_le_bytes());
+        }
+        let mut ssect = vec![];
+        for subsector in &map.subsectors {
+            ssect.extend(subsector.seg_count.to_le_bytes());
+            ssect.extend(subsector.first_seg.to_le_bytes());
+        }
+        let mut nodes = vec![];
+        for node in &map.nodes {
+            for v in [node.x, node.y, node.dx, node.dy] {
+                nodes.extend(v.to_le_bytes());
+            }
+            bbox_bytes(&node.right_bbox, &mut nodes);
+            bbox_bytes(&node.left_bbox, &mut nodes);
+            nodes.extend(raw_child(node.right).to_le_bytes());
+            nodes.extend(raw_child(node.left).to_le_bytes());
+        }
+        [
+            ("GL_MAP01", vec![]),
+            ("GL_VERT", vert),
+            ("GL_SEGS", segs),
+            ("GL_SSECT", ssect),
+            ("GL_NODES", nodes),
+            ("GL_PVS", vec![]),
+        ]
+        .into_iter()
+        .map(|(name, data)| Lump::new(LumpName::known(name), data))
+        .collect()
+    }
+
+    /// `map` without its nodes or their split vertices.
+    fn unbuilt(map: &Map, original: u32) -> Map {
+        let mut vanilla = map.clone();
+        vanilla.vertexes.truncate(original as usize);
+        vanilla.segs.clear();
+        vanilla.subsectors.clear();
+        vanilla.nodes.clear();
+        vanilla
+    }
+
+    #[test]
+    fn test_gl_lumps() {
+        let (map, original) = built();
+        let mut vanilla = unbuilt(&map, original);
+        let mut builder = WadBuilder::pwad();
+        builder.lumps.extend(vanilla.to_lumps().unwrap());
+        builder.lumps.extend(gl_lumps(&map, original));
+        let wad = decode(&builder);
+
+        let nodes = ExtendedNodes::from_wad(&wad, "MAP01").unwrap().unwrap();
+        assert_eq!(nodes.format, NodeFormat::Gl(5));
+        assert_eq!(nodes.subsectors, map.subsectors);
+        assert_eq!(nodes.nodes, map.nodes);
+        vanilla.apply_nodes(nodes).unwrap();
+        assert_eq!(vanilla.vertexes, map.vertexes);
+        assert_eq!(vanilla.segs, map.segs);
+    }
+
+    #[test]
+    fn test_gl_nodes_only_replace_missing_vanilla_nodes() {
+        let (map, original) = built();
+        let mut gl_map = map.clone();
+        // Stands in for the minisegs glBSP adds along partition lines.
+        gl_map.segs[0].linedef = NO_LINEDEF;
+        let mut builder = WadBuilder::pwad();
+        builder.lumps.extend(map.to_lumps().unwrap());
+        builder.lumps.extend(gl_lumps(&gl_map, original));
+        let wad = decode(&builder);
+        let decoded = Map::from_wad(&wad, "MAP01").unwrap();
+        assert_eq!(decoded.segs, map.segs);
+        assert!(decoded.to_lumps().is_ok());
+    }
+
+    #[test]
+    fn test_gl_nodes_after_staged_map() {
+        let (map, original) = built();
+        let mut wad = decode(&WadBuilder::pwad());
+        unbuilt(&map, original).write_to_wad(&mut wad).unwrap();
+        for lump in gl_lumps(&map, original) {
+            wad.push_lump(lump.name, lump.data);
+        }
+        let decoded = Map::from_wad(&wad, "MAP01").unwrap();
+        assert_eq!(decoded.vertexes, map.vertexes);
+        assert_eq!(decoded.nodes, map.nodes);
+    }
+}
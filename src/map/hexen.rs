@@ -0,0 +1,112 @@
+//! Hexen-format map records, as used by Hexen and ZDoom-family ports.
+
+use crate::raw::{array_at, i16_at, u16_at, u8_at};
+
+use super::doom::{sidedef, Record, NO_SIDEDEF};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HexenThing {
+    pub tid: i16,
+    pub x: i16,
+    pub y: i16,
+    pub z: i16,
+    pub angle: i16,
+    pub kind: i16,
+    pub flags: i16,
+    pub special: u8,
+    pub args: [u8; 5],
+}
+impl Record for HexenThing {
+    const SIZE: usize = 20;
+
+    fn decode(bytes: &[u8]) -> HexenThing {
+        HexenThing {
+            tid: i16_at(bytes, 0),
+            x: i16_at(bytes, 2),
+            y: i16_at(bytes, 4),
+            z: i16_at(bytes, 6),
+            angle: i16_at(bytes, 8),
+            kind: i16_at(bytes, 10),
+            flags: i16_at(bytes, 12),
+            special: u8_at(bytes, 14),
+            args: array_at(bytes, 15),
+        }
+    }
+
+    fn encode(&self, out: &mut Vec<u8>) {
+        out.extend(self.tid.to_le_bytes());
+        out.extend(self.x.to_le_bytes());
+        out.extend(self.y.to_le_bytes());
+        out.extend(self.z.to_le_bytes());
+        out.extend(self.angle.to_le_bytes());
+        out.extend(self.kind.to_le_bytes());
+        out.extend(self.flags.to_le_bytes());
+        out.push(self.special);
+        out.extend(self.args);
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HexenLinedef {
+    pub v1: u16,
+    pub v2: u16,
+    pub flags: i16,
+    pub special: u8,
+    pub args: [u8; 5],
+    pub right: Option<u16>,
+    pub left: Option<u16>,
+}
+impl Record for HexenLinedef {
+    const SIZE: usize = 16;
+
+    fn decode(bytes: &[u8]) -> HexenLinedef {
+        HexenLinedef {
+            v1: u16_at(bytes, 0),
+            v2: u16_at(bytes, 2),
+            flags: i16_at(bytes, 4),
+            special: u8_at(bytes, 6),
+            args: array_at(bytes, 7),
+            right: sidedef(u16_at(bytes, 12)),
+            left: sidedef(u16_at(bytes, 14)),
+        }
+    }
+
+    fn encode(&self, out: &mut Vec<u8>) {
+        out.extend(self.v1.to_le_bytes());
+        out.extend(self.v2.to_le_bytes());
+        out.extend(self.flags.to_le_bytes());
+        out.push(self.special);
+        out.extend(self.args);
+        out.extend(self.right.unwrap_or(NO_SIDEDEF).to_le_bytes());
+        out.extend(self.left.unwrap_or(NO_SIDEDEF).to_le_bytes());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hexen_thing() {
+        let bytes = [
+            5, 0, 0x80, 0, 0x40, 0, 8, 0, 90, 0, 1, 0, 7, 0, 80, 1, 2, 3, 4, 5,
+        ];
+        let thing = HexenThing::decode(&bytes);
+        assert_eq!(thing.tid, 5);
+        assert_eq!((thing.x, thing.y, thing.z), (128, 64, 8));
+        assert_eq!(thing.kind, 1);
+        assert_eq!(thing.special, 80);
+        assert_eq!(thing.args, [1, 2, 3, 4, 5]);
+    }
+
+    #[test]
+    fn test_hexen_linedef() {
+        let bytes = [1, 0, 2, 0, 0, 2, 80, 9, 0, 0, 0, 0, 3, 0, 0xFF, 0xFF];
+        let linedef = HexenLinedef::decode(&bytes);
+        assert_eq!((linedef.v1, linedef.v2), (1, 2));
+        assert_eq!(linedef.flags, 0x200);
+        assert_eq!(linedef.special, 80);
+        assert_eq!(linedef.args[0], 9);
+        assert_eq!((linedef.right, linedef.left), (Some(3), None));
+    }
+}
@@ -0,0 +1,308 @@
+use crate::{LumpName, LumpReadError, Wad};
+
+use super::{
+    doom::{decode_records, Record},
+    Blockmap, ExtendedNodes, HexenLinedef, HexenThing, Linedef, MapFormat, Node, Reject, Sector,
+    Seg, Sidedef, SubSector, Thing, Vertex,
+};
+
+#[derive(Debug)]
+pub enum MapDecodeError {
+    NoSuchMap(String),
+    UnsupportedFormat(MapFormat),
+    MissingLump(&'static str),
+    FailedToReadLump(LumpReadError),
+    BadLumpSize { lump: &'static str, size: usize },
+    Udmf(super::udmf::UdmfError),
+    Nodes(super::NodeDecodeError),
+}
+
+/// Damage `Map::from_wad` works around rather than failing on.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MapWarning {
+    /// A BLOCKMAP that does not decode; engines build their own.
+    BadBlockmap { size: usize },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Things {
+    Doom(Vec<Thing>),
+    Hexen(Vec<HexenThing>),
+}
+impl Things {
+    pub fn len(&self) -> usize {
+        match self {
+            Things::Doom(things) => things.len(),
+            Things::Hexen(things) => things.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// The fields Doom and Hexen linedefs share.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LineGeometry {
+    pub v1: u16,
+    pub v2: u16,
+    pub flags: i16,
+    pub right: Option<u16>,
+    pub left: Option<u16>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Linedefs {
+    Doom(Vec<Linedef>),
+    Hexen(Vec<HexenLinedef>),
+}
+impl Linedefs {
+    pub fn len(&self) -> usize {
+        match self {
+            Linedefs::Doom(linedefs) => linedefs.len(),
+            Linedefs::Hexen(linedefs) => linedefs.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn geometry(&self) -> Vec<LineGeometry> {
+        match self {
+            Linedefs::Doom(linedefs) => linedefs
+                .iter()
+                .map(|l| LineGeometry {
+                    v1: l.v1,
+                    v2: l.v2,
+                    flags: l.flags,
+                    right: l.right,
+                    left: l.left,
+                })
+                .collect(),
+            Linedefs::Hexen(linedefs) => linedefs
+                .iter()
+                .map(|l| LineGeometry {
+                    v1: l.v1,
+                    v2: l.v2,
+                    flags: l.flags,
+                    right: l.right,
+                    left: l.left,
+                })
+                .collect(),
+        }
+    }
+}
+
+/// A decoded level.
+#[derive(Debug, Clone)]
+pub struct Map {
+    pub name: LumpName,
+    pub format: MapFormat,
+    pub things: Things,
+    pub linedefs: Linedefs,
+    pub sidedefs: Vec<Sidedef>,
+    pub vertexes: Vec<Vertex>,
+    pub segs: Vec<Seg>,
+    pub subsectors: Vec<SubSector>,
+    pub nodes: Vec<Node>,
+    pub sectors: Vec<Sector>,
+    pub reject: Option<Reject>,
+    pub blockmap: Option<Blockmap>,
+    /// Compiled ACS of Hexen-format maps.
+    pub behavior: Option<Vec<u8>>,
+    pub warnings: Vec<MapWarning>,
+}
+impl Map {
+    /// Decodes a map by marker name. SEGS, SSECTORS and NODES may be absent
+    /// in maps that were never run through a node builder, and decode empty.
+    /// ZDoom extended nodes are used in their place when present, and glBSP
+    /// nodes from the map's `GL_` group only when the vanilla SEGS or
+    /// SSECTORS are missing or empty. A malformed BLOCKMAP is dropped with a
+    /// warning.
+    pub fn from_wad(wad: &Wad, name: &str) -> Result<Map, MapDecodeError> {
+        let lumps = wad
+            .find_map(name)
+            .ok_or_else(|| MapDecodeError::NoSuchMap(name.into()))?;
+        let (things, linedefs) = match lumps.format {
+            MapFormat::Doom => (
+                Things::Doom(required(wad, lumps.things, "THINGS")?),
+                Linedefs::Doom(required(wad, lumps.linedefs, "LINEDEFS")?),
+            ),
+            MapFormat::Hexen => (
+                Things::Hexen(required(wad, lumps.things, "THINGS")?),
+                Linedefs::Hexen(required(wad, lumps.linedefs, "LINEDEFS")?),
+            ),
+            format => return Err(MapDecodeError::UnsupportedFormat(format)),
+        };
+        let read = |index: Option<usize>| {
+            index
+                .map(|index| wad.read_lump(index))
+                .transpose()
+                .map_err(MapDecodeError::FailedToReadLump)
+        };
+        let behavior = read(lumps.behavior)?;
+        // ZDoom builders put extended nodes in NODES or SSECTORS and leave
+        // the other node lumps empty.
+        let extended = ExtendedNodes::from_node_lumps(wad, &lumps)?;
+        let sectors: Vec<Sector> = required(wad, lumps.sectors, "SECTORS")?;
+        let reject = read(lumps.reject)?.map(|data| Reject::decode(&data, sectors.len()));
+        let mut warnings = vec![];
+        let blockmap = match read(lumps.blockmap)? {
+            Some(data) if !data.is_empty() => {
+                let blockmap = Blockmap::decode(&data);
+                if blockmap.is_none() {
+                    warnings.push(MapWarning::BadBlockmap { size: data.len() });
+                }
+                blockmap
+            }
+            _ => None,
+        };
+        let mut map = Map {
+            name: lumps.name,
+            format: lumps.format,
+            things,
+            linedefs,
+            sidedefs: required(wad, lumps.sidedefs, "SIDEDEFS")?,
+            vertexes: required(wad, lumps.vertexes, "VERTEXES")?,
+            segs: vec![],
+            subsectors: vec![],
+            nodes: vec![],
+            sectors,
+            reject,
+            blockmap,
+            behavior,
+            warnings,
+        };
+        match extended {
+            Some(nodes) => map.apply_nodes(nodes).map_err(MapDecodeError::Nodes)?,
+            None => {
+                map.segs = optional(wad, lumps.segs, "SEGS")?;
+                map.subsectors = optional(wad, lumps.ssectors, "SSECTORS")?;
+                map.nodes = optional(wad, lumps.nodes, "NODES")?;
+                // glBSP's GL nodes hold minisegs vanilla can't store, so they
+                // only stand in for missing vanilla nodes.
+                if map.segs.is_empty() || map.subsectors.is_empty() {
+                    let vertices = map.vertexes.len() as u32;
+                    if let Some(nodes) = ExtendedNodes::from_gl_group(wad, &lumps, vertices)? {
+                        map.apply_nodes(nodes).map_err(MapDecodeError::Nodes)?;
+                    }
+                }
+            }
+        }
+        Ok(map)
+    }
+}
+
+fn optional<T: Record>(
+    wad: &Wad,
+    index: Option<usize>,
+    lump: &'static str,
+) -> Result<Vec<T>, MapDecodeError> {
+    match index {
+        Some(index) => {
+            let data = wad
+                .read_lump(index)
+                .map_err(MapDecodeError::FailedToReadLump)?;
+            decode_records(&data).ok_or(MapDecodeError::BadLumpSize {
+                lump,
+                size: data.len(),
+            })
+        }
+        None => Ok(vec![]),
+    }
+}
+
+fn required<T: Record>(
+    wad: &Wad,
+    index: Option<usize>,
+    lump: &'static str,
+) -> Result<Vec<T>, MapDecodeError> {
+    index.ok_or(MapDecodeError::MissingLump(lump))?;
+    optional(wad, index, lump)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::{decode, name};
+    use crate::WadBuilder;
+
+    #[test]
+    fn test_from_wad() {
+        let mut vertexes = vec![];
+        for (x, y) in [(0_i16, 0_i16), (64, 0), (64, 64)] {
+            vertexes.extend(x.to_le_bytes());
+            vertexes.extend(y.to_le_bytes());
+        }
+        let mut sector = vec![0, 0, 128, 0];
+        sector.extend(b"FLOOR4_8CEIL3_5\0");
+        sector.extend([160, 0, 0, 0, 0, 0]);
+        let wad = decode(
+            &WadBuilder::pwad()
+                .lump(name("E1M1"), vec![])
+                .lump(name("THINGS"), vec![32, 0, 16, 0, 90, 0, 1, 0, 7, 0])
+                .lump(
+                    name("LINEDEFS"),
+                    vec![0, 0, 1, 0, 1, 0, 0, 0, 0, 0, 0, 0, 0xFF, 0xFF],
+                )
+                .lump(name("SIDEDEFS"), vec![0; 30])
+                .lump(name("VERTEXES"), vertexes)
+                .lump(name("SECTORS"), sector),
+        );
+        let map = Map::from_wad(&wad, "E1M1").unwrap();
+        let Things::Doom(things) = &map.things else {
+            panic!("expected Doom things, found {:?}", map.things)
+        };
+        assert_eq!(things[0].kind, 1);
+        assert_eq!(things[0].angle, 90);
+        assert_eq!(map.linedefs.geometry()[0].v2, 1);
+        assert!(map.behavior.is_none());
+        assert_eq!(map.vertexes[2], Vertex { x: 64, y: 64 });
+        assert_eq!(map.sectors[0].ceiling_texture.to_string(), "CEIL3_5");
+        assert_eq!(map.sectors[0].light, 160);
+        assert!(map.nodes.is_empty());
+        assert!(map.warnings.is_empty());
+        assert!(matches!(
+            Map::from_wad(&wad, "E1M2"),
+            Err(MapDecodeError::NoSuchMap(_))
+        ));
+    }
+
+    #[test]
+    fn test_hexen_from_wad() {
+        let mut thing = vec![0; 20];
+        thing[0] = 42;
+        let wad = decode(
+            &WadBuilder::pwad()
+                .lump(name("MAP01"), vec![])
+                .lump(name("THINGS"), thing)
+                .lump(
+                    name("LINEDEFS"),
+                    vec![0, 0, 0, 0, 0, 0, 80, 0, 0, 0, 0, 0, 0, 0, 0xFF, 0xFF],
+                )
+                .lump(name("SIDEDEFS"), vec![0; 30])
+                .lump(name("VERTEXES"), vec![0; 4])
+                .lump(name("SECTORS"), vec![0; 26])
+                .lump(name("BLOCKMAP"), vec![0; 6])
+                .lump(name("BEHAVIOR"), b"ACS\0".to_vec()),
+        );
+        let map = Map::from_wad(&wad, "MAP01").unwrap();
+        assert_eq!(map.format, MapFormat::Hexen);
+        assert_eq!(
+            map.things,
+            Things::Hexen(vec![HexenThing {
+                tid: 42,
+                ..HexenThing::decode(&[0; 20])
+            }])
+        );
+        let Linedefs::Hexen(linedefs) = &map.linedefs else {
+            panic!("expected Hexen linedefs, found {:?}", map.linedefs)
+        };
+        assert_eq!(linedefs[0].special, 80);
+        assert_eq!(map.behavior.as_deref(), Some(&b"ACS\0"[..]));
+        assert_eq!(map.blockmap, None);
+        assert_eq!(map.warnings, [MapWarning::BadBlockmap { size: 6 }]);
+    }
+}
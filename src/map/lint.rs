@@ -0,0 +1,404 @@
+//! Structural checks for decoded maps.
+
+use std::collections::{HashMap, HashSet};
+
+use crate::LumpName;
+
+use super::{Map, MapFormat, Things};
+
+/// The highest count vanilla can index with its signed 16-bit fields.
+const VANILLA_LIMIT: usize = 0x7FFF;
+/// Vanilla's static visplane array.
+const MAX_VISPLANES: usize = 128;
+/// Distinct planes around a single sector past which a view into it is
+/// likely to overflow the visplane array.
+const CROWDED_PLANES: usize = MAX_VISPLANES / 2;
+
+/// Thing types known to Doom and Doom II.
+const DOOM_THING_TYPES: &[std::ops::RangeInclusive<i16>] = &[
+    1..=89,
+    2001..=2008,
+    2010..=2015,
+    2018..=2019,
+    2022..=2026,
+    2028..=2028,
+    2035..=2035,
+    2045..=2049,
+    3001..=3006,
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    /// Likely to render wrong or behave oddly.
+    Warning,
+    /// Crashes or refuses to load in vanilla engines.
+    Error,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum MapObject {
+    Map,
+    Thing(usize),
+    Linedef(usize),
+    Sidedef(usize),
+    Sector(usize),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Problem {
+    /// The sector's boundary does not close at `(x, y)`.
+    UnclosedSector {
+        x: i16,
+        y: i16,
+    },
+    MissingVertex(u16),
+    MissingSidedef(u16),
+    /// A linedef without a right side.
+    NoRightSidedef,
+    MissingSector(u16),
+    ZeroLengthLine,
+    MissingPlayerStart,
+    MissingTexture(LumpName),
+    MissingFlat(LumpName),
+    /// A one-sided line with no middle texture.
+    NoMiddleTexture,
+    UnknownThingType(i16),
+    LimitExceeded {
+        lump: &'static str,
+        count: usize,
+        limit: usize,
+    },
+    BlockmapTooLarge,
+    /// Too many distinct floor and ceiling planes that can be seen
+    /// together; a heuristic only.
+    VisplaneRisk {
+        planes: usize,
+    },
+}
+impl Problem {
+    pub fn severity(&self) -> Severity {
+        match self {
+            Problem::ZeroLengthLine
+            | Problem::MissingTexture(_)
+            | Problem::MissingFlat(_)
+            | Problem::NoMiddleTexture
+            | Problem::UnknownThingType(_)
+            | Problem::VisplaneRisk { .. } => Severity::Warning,
+            _ => Severity::Error,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub map: LumpName,
+    pub object: MapObject,
+    pub problem: Problem,
+}
+impl Diagnostic {
+    pub fn severity(&self) -> Severity {
+        self.problem.severity()
+    }
+}
+
+/// What the checker compares against. Texture and flat checks are skipped
+/// without a list of known names; thing types default to Doom's for
+/// Doom-format maps and are skipped for Hexen ones.
+#[derive(Debug, Clone, Default)]
+pub struct LintOptions {
+    pub textures: Option<HashSet<LumpName>>,
+    pub flats: Option<HashSet<LumpName>>,
+    pub thing_types: Option<HashSet<i16>>,
+}
+
+struct Report<'a> {
+    map: &'a Map,
+    diagnostics: Vec<Diagnostic>,
+}
+impl Report<'_> {
+    fn push(&mut self, object: MapObject, problem: Problem) {
+        self.diagnostics.push(Diagnostic {
+            map: self.map.name,
+            object,
+            problem,
+        });
+    }
+}
+
+fn is_blank(texture: &LumpName) -> bool {
+    let name = texture.to_string();
+    name.is_empty() || name == "-"
+}
+
+impl Map {
+    pub fn lint(&self, options: &LintOptions) -> Vec<Diagnostic> {
+        let mut report = Report {
+            map: self,
+            diagnostics: vec![],
+        };
+        self.lint_lines(options, &mut report);
+        self.lint_sectors(options, &mut report);
+        self.lint_things(options, &mut report);
+        self.lint_limits(&mut report);
+        report.diagnostics
+    }
+
+    fn lint_lines(&self, options: &LintOptions, report: &mut Report) {
+        for (index, line) in self.linedefs.geometry().iter().enumerate() {
+            let object = MapObject::Linedef(index);
+            let v1 = self.vertexes.get(line.v1 as usize);
+            let v2 = self.vertexes.get(line.v2 as usize);
+            for (vertex, found) in [(line.v1, v1), (line.v2, v2)] {
+                if found.is_none() {
+                    report.push(object, Problem::MissingVertex(vertex));
+                }
+            }
+            if let (Some(v1), Some(v2)) = (v1, v2) {
+                if v1 == v2 {
+                    report.push(object, Problem::ZeroLengthLine);
+                }
+            }
+            match line.right {
+                None => report.push(object, Problem::NoRightSidedef),
+                Some(side) if self.sidedefs.get(side as usize).is_none() => {
+                    report.push(object, Problem::MissingSidedef(side))
+                }
+                Some(side) if line.left.is_none() => {
+                    if is_blank(&self.sidedefs[side as usize].middle) {
+                        report.push(MapObject::Sidedef(side as usize), Problem::NoMiddleTexture);
+                    }
+                }
+                Some(_) => {}
+            }
+            if let Some(side) = line.left {
+                if self.sidedefs.get(side as usize).is_none() {
+                    report.push(object, Problem::MissingSidedef(side));
+                }
+            }
+        }
+
+        for (index, side) in self.sidedefs.iter().enumerate() {
+            let object = MapObject::Sidedef(index);
+            if side.sector as usize >= self.sectors.len() {
+                report.push(object, Problem::MissingSector(side.sector));
+            }
+            if let Some(textures) = &options.textures {
+                for texture in [side.upper, side.middle, side.lower] {
+                    if !is_blank(&texture) && !textures.contains(&texture) {
+                        report.push(object, Problem::MissingTexture(texture));
+                    }
+                }
+            }
+        }
+    }
+
+    fn lint_sectors(&self, options: &LintOptions, report: &mut Report) {
+        if let Some(flats) = &options.flats {
+            for (index, sector) in self.sectors.iter().enumerate() {
+                for flat in [sector.floor_texture, sector.ceiling_texture] {
+                    if !flats.contains(&flat) {
+                        report.push(MapObject::Sector(index), Problem::MissingFlat(flat));
+                    }
+                }
+            }
+        }
+
+        // Walking each sector's boundary with the sector on the right, every
+        // point is left as often as it is entered.
+        let mut balance: Vec<HashMap<(i16, i16), i32>> = vec![HashMap::new(); self.sectors.len()];
+        let mut neighbours: Vec<HashSet<usize>> = vec![HashSet::new(); self.sectors.len()];
+        let sector_of = |side: Option<u16>| {
+            let sector = self.sidedefs.get(side? as usize)?.sector as usize;
+            (sector < self.sectors.len()).then_some(sector)
+        };
+        for line in self.linedefs.geometry() {
+            let (Some(v1), Some(v2)) = (
+                self.vertexes.get(line.v1 as usize),
+                self.vertexes.get(line.v2 as usize),
+            ) else {
+                continue;
+            };
+            let (right, left) = (sector_of(line.right), sector_of(line.left));
+            for (sector, from, to) in [(right, v1, v2), (left, v2, v1)] {
+                if let Some(sector) = sector {
+                    *balance[sector].entry((from.x, from.y)).or_default() += 1;
+                    *balance[sector].entry((to.x, to.y)).or_default() -= 1;
+                }
+            }
+            if let (Some(right), Some(left)) = (right, left) {
+                neighbours[right].insert(left);
+                neighbours[left].insert(right);
+            }
+        }
+        for (index, points) in balance.iter().enumerate() {
+            let open = points.iter().filter(|(_, &count)| count != 0).min();
+            if let Some((&(x, y), _)) = open {
+                report.push(MapObject::Sector(index), Problem::UnclosedSector { x, y });
+            }
+        }
+
+        let planes = |sector: usize| {
+            let s = &self.sectors[sector];
+            [
+                (s.floor_height, s.floor_texture, s.light),
+                (s.ceiling_height, s.ceiling_texture, s.light),
+            ]
+        };
+        let all: HashSet<_> = (0..self.sectors.len()).flat_map(planes).collect();
+        if all.len() > MAX_VISPLANES {
+            report.push(MapObject::Map, Problem::VisplaneRisk { planes: all.len() });
+        }
+        for (index, around) in neighbours.iter().enumerate() {
+            let nearby: HashSet<_> = around
+                .iter()
+                .copied()
+                .chain([index])
+                .flat_map(planes)
+                .collect();
+            if nearby.len() > CROWDED_PLANES {
+                report.push(
+                    MapObject::Sector(index),
+                    Problem::VisplaneRisk {
+                        planes: nearby.len(),
+                    },
+                );
+            }
+        }
+    }
+
+    fn lint_things(&self, options: &LintOptions, report: &mut Report) {
+        let kinds: Vec<i16> = match &self.things {
+            Things::Doom(things) => things.iter().map(|t| t.kind).collect(),
+            Things::Hexen(things) => things.iter().map(|t| t.kind).collect(),
+        };
+        if !kinds.contains(&1) {
+            report.push(MapObject::Map, Problem::MissingPlayerStart);
+        }
+        let known = |kind: i16| match &options.thing_types {
+            Some(types) => types.contains(&kind),
+            None if self.format == MapFormat::Doom => {
+                DOOM_THING_TYPES.iter().any(|range| range.contains(&kind))
+            }
+            None => true,
+        };
+        for (index, &kind) in kinds.iter().enumerate() {
+            if !known(kind) {
+                report.push(MapObject::Thing(index), Problem::UnknownThingType(kind));
+            }
+        }
+    }
+
+    fn lint_limits(&self, report: &mut Report) {
+        for (lump, count) in [
+            ("LINEDEFS", self.linedefs.len()),
+            ("SIDEDEFS", self.sidedefs.len()),
+            ("VERTEXES", self.vertexes.len()),
+            ("SEGS", self.segs.len()),
+            ("SSECTORS", self.subsectors.len()),
+            ("NODES", self.nodes.len()),
+            ("SECTORS", self.sectors.len()),
+        ] {
+            if count > VANILLA_LIMIT {
+                let limit = VANILLA_LIMIT;
+                report.push(
+                    MapObject::Map,
+                    Problem::LimitExceeded { lump, count, limit },
+                );
+            }
+        }
+        if self.blockmap.as_ref().is_some_and(|b| b.encode().is_err()) {
+            report.push(MapObject::Map, Problem::BlockmapTooLarge);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::map::testutil::{self, Sketch};
+
+    fn problems(map: &Map, options: &LintOptions) -> Vec<(MapObject, Problem)> {
+        map.lint(options)
+            .into_iter()
+            .map(|d| (d.object, d.problem))
+            .collect()
+    }
+
+    #[test]
+    fn test_clean_map() {
+        let map = testutil::two_rooms();
+        let names = |names: &[&str]| Some(names.iter().map(|n| LumpName::known(n)).collect());
+        let options = LintOptions {
+            textures: names(&["STARTAN2"]),
+            flats: names(&["FLOOR4_8", "CEIL3_5"]),
+            thing_types: None,
+        };
+        assert_eq!(problems(&map, &options), []);
+    }
+
+    #[test]
+    fn test_structural_problems() {
+        let mut sketch = Sketch::default();
+        let sector = sketch.sector();
+        // Three walls of a square and a degenerate line.
+        sketch.line((0, 0), (0, 64), sector, None);
+        sketch.line((0, 64), (64, 64), sector, None);
+        sketch.line((64, 64), (64, 0), sector, None);
+        sketch.line((64, 0), (64, 0), sector, None);
+        sketch.thing(32, 32, 3);
+        sketch.thing(16, 16, 9999);
+        let mut map = sketch.finish();
+        map.sidedefs[1].sector = 7;
+        map.sidedefs[2].middle = LumpName::known("NOSUCH");
+        if let crate::map::Linedefs::Doom(lines) = &mut map.linedefs {
+            lines[0].right = Some(40);
+        }
+        let options = LintOptions {
+            textures: Some([LumpName::known("STARTAN2")].into()),
+            ..Default::default()
+        };
+        let found = problems(&map, &options);
+        let expected = [
+            (MapObject::Linedef(0), Problem::MissingSidedef(40)),
+            (MapObject::Linedef(3), Problem::ZeroLengthLine),
+            (MapObject::Sidedef(1), Problem::MissingSector(7)),
+            (
+                MapObject::Sidedef(2),
+                Problem::MissingTexture(LumpName::known("NOSUCH")),
+            ),
+            (
+                MapObject::Sector(0),
+                Problem::UnclosedSector { x: 64, y: 0 },
+            ),
+            (MapObject::Map, Problem::MissingPlayerStart),
+            (MapObject::Thing(1), Problem::UnknownThingType(9999)),
+        ];
+        assert_eq!(found, expected);
+        let diagnostic = &map.lint(&options)[0];
+        assert_eq!(diagnostic.map, LumpName::known("MAP01"));
+        assert_eq!(diagnostic.severity(), Severity::Error);
+    }
+
+    #[test]
+    fn test_visplane_heuristic() {
+        let mut sketch = Sketch::default();
+        let hub = sketch.sector();
+        for i in 0..70 {
+            let step = sketch.sector();
+            let x = i * 16;
+            sketch.line((x, 0), (x + 16, 0), hub, Some(step));
+        }
+        let mut map = sketch.finish();
+        for (i, sector) in map.sectors.iter_mut().enumerate() {
+            sector.floor_height = i as i16 * 8;
+        }
+        let risks: Vec<_> = map
+            .lint(&LintOptions::default())
+            .into_iter()
+            .filter(|d| matches!(d.problem, Problem::VisplaneRisk { .. }))
+            .collect();
+        assert_eq!(risks.len(), 1);
+        assert_eq!(risks[0].object, MapObject::Sector(0));
+        assert_eq!(risks[0].problem, Problem::VisplaneRisk { planes: 72 });
+    }
+}
@@ -0,0 +1,212 @@
+use std::ops::Range;
+
+use crate::{LumpName, Wad};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MapFormat {
+    /// Binary lumps in the vanilla Doom layout.
+    Doom,
+    /// Binary lumps with Hexen THINGS and LINEDEFS, marked by a `BEHAVIOR`
+    /// lump.
+    Hexen,
+    /// A `TEXTMAP` lump terminated by `ENDMAP`.
+    Udmf,
+}
+
+/// Directory positions of a map's marker and component lumps.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MapLumps {
+    pub name: LumpName,
+    pub format: MapFormat,
+    /// The marker and every lump that belongs to the map.
+    pub range: Range<usize>,
+    pub things: Option<usize>,
+    pub linedefs: Option<usize>,
+    pub sidedefs: Option<usize>,
+    pub vertexes: Option<usize>,
+    pub segs: Option<usize>,
+    pub ssectors: Option<usize>,
+    pub nodes: Option<usize>,
+    pub sectors: Option<usize>,
+    pub reject: Option<usize>,
+    pub blockmap: Option<usize>,
+    pub behavior: Option<usize>,
+    pub scripts: Option<usize>,
+    pub textmap: Option<usize>,
+    pub znodes: Option<usize>,
+    pub dialogue: Option<usize>,
+    pub endmap: Option<usize>,
+}
+impl MapLumps {
+    fn new(name: LumpName, marker: usize, format: MapFormat) -> MapLumps {
+        MapLumps {
+            name,
+            format,
+            range: marker..marker + 1,
+            things: None,
+            linedefs: None,
+            sidedefs: None,
+            vertexes: None,
+            segs: None,
+            ssectors: None,
+            nodes: None,
+            sectors: None,
+            reject: None,
+            blockmap: None,
+            behavior: None,
+            scripts: None,
+            textmap: None,
+            znodes: None,
+            dialogue: None,
+            endmap: None,
+        }
+    }
+
+    pub fn marker(&self) -> usize {
+        self.range.start
+    }
+
+    fn slot(&mut self, component: &str) -> Option<&mut Option<usize>> {
+        let udmf = self.format == MapFormat::Udmf;
+        Some(match component {
+            "THINGS" if !udmf => &mut self.things,
+            "LINEDEFS" if !udmf => &mut self.linedefs,
+            "SIDEDEFS" if !udmf => &mut self.sidedefs,
+            "VERTEXES" if !udmf => &mut self.vertexes,
+            "SEGS" if !udmf => &mut self.segs,
+            "SSECTORS" if !udmf => &mut self.ssectors,
+            "NODES" if !udmf => &mut self.nodes,
+            "SECTORS" if !udmf => &mut self.sectors,
+            "REJECT" => &mut self.reject,
+            "BLOCKMAP" => &mut self.blockmap,
+            "BEHAVIOR" => &mut self.behavior,
+            "SCRIPTS" => &mut self.scripts,
+            "TEXTMAP" if udmf => &mut self.textmap,
+            "ZNODES" if udmf => &mut self.znodes,
+            "DIALOGUE" if udmf => &mut self.dialogue,
+            "ENDMAP" if udmf => &mut self.endmap,
+            _ => return None,
+        })
+    }
+}
+
+fn is_classic_map_name(name: &str) -> bool {
+    let bytes = name.as_bytes();
+    match bytes {
+        [b'E', e, b'M', m] => e.is_ascii_digit() && m.is_ascii_digit(),
+        [b'M', b'A', b'P', rest @ ..] => !rest.is_empty() && rest.iter().all(u8::is_ascii_digit),
+        _ => false,
+    }
+}
+
+const BINARY_COMPONENTS: [&str; 12] = [
+    "THINGS", "LINEDEFS", "SIDEDEFS", "VERTEXES", "SEGS", "SSECTORS", "NODES", "SECTORS", "REJECT",
+    "BLOCKMAP", "BEHAVIOR", "SCRIPTS",
+];
+
+impl Wad {
+    fn upper_name(&self, index: usize) -> Option<String> {
+        self.directory
+            .get(index)
+            .map(|entry| entry.name.to_string().to_ascii_uppercase())
+    }
+
+    /// Recognizes a map marker at `index`: `ExMy` and `MAPxx` followed by any
+    /// map lump, or an arbitrary name directly followed by `THINGS`,
+    /// `LINEDEFS` or `TEXTMAP`.
+    fn map_at(&self, index: usize) -> Option<MapLumps> {
+        let marker = self.upper_name(index)?;
+        let next = self.upper_name(index + 1)?;
+        let format = match next.as_str() {
+            "TEXTMAP" => MapFormat::Udmf,
+            "THINGS" | "LINEDEFS" => MapFormat::Doom,
+            other if is_classic_map_name(&marker) && BINARY_COMPONENTS.contains(&other) => {
+                MapFormat::Doom
+            }
+            _ => return None,
+        };
+        let mut map = MapLumps::new(self.directory[index].name, index, format);
+        let mut i = index + 1;
+        while let Some(name) = self.upper_name(i) {
+            match map.slot(&name) {
+                Some(slot) if slot.is_none() => *slot = Some(i),
+                // A repeated lump belongs to the next map; unknown lumps end
+                // a binary map but are skipped over inside TEXTMAP..ENDMAP.
+                _ if format == MapFormat::Doom => break,
+                Some(_) => break,
+                None => {}
+            }
+            i += 1;
+            if map.endmap.is_some() {
+                break;
+            }
+        }
+        map.range.end = i;
+        if map.format == MapFormat::Doom && map.behavior.is_some() {
+            map.format = MapFormat::Hexen;
+        }
+        Some(map)
+    }
+
+    /// Every map in directory order.
+    pub fn maps(&self) -> Vec<MapLumps> {
+        let mut maps = vec![];
+        let mut i = 0;
+        while i < self.directory.len() {
+            match self.map_at(i) {
+                Some(map) => {
+                    i = map.range.end;
+                    maps.push(map);
+                }
+                None => i += 1,
+            }
+        }
+        maps
+    }
+
+    /// Last-wins lookup of a map by its marker name.
+    pub fn find_map(&self, name: &str) -> Option<MapLumps> {
+        self.find_all_indices(name)
+            .iter()
+            .rev()
+            .find_map(|&i| self.map_at(i))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::wad_of;
+
+    #[test]
+    fn test_group_maps() {
+        let wad = wad_of(&[
+            "PLAYPAL", "E1M1", "THINGS", "LINEDEFS", "SIDEDEFS", "VERTEXES", "SECTORS", "E1M2",
+            "VERTEXES", "THINGS", "MYLEVEL", "THINGS", "BEHAVIOR", "MAP01", "TEXTMAP", "ZNODES",
+            "UNKNOWN", "ENDMAP", "MAP02", "ENDOOM",
+        ]);
+        let maps = wad.maps();
+        let names: Vec<String> = maps.iter().map(|m| m.name.to_string()).collect();
+        assert_eq!(names, ["E1M1", "E1M2", "MYLEVEL", "MAP01"]);
+        assert_eq!(maps[0].range, 1..7);
+        assert_eq!(maps[0].sectors, Some(6));
+        assert_eq!(maps[0].segs, None);
+        assert_eq!(maps[1].vertexes, Some(8));
+        assert_eq!(maps[2].behavior, Some(12));
+        assert_eq!(maps[2].format, MapFormat::Hexen);
+        assert_eq!(maps[3].format, MapFormat::Udmf);
+        assert_eq!(maps[3].znodes, Some(15));
+        assert_eq!(maps[3].endmap, Some(17));
+        assert_eq!(maps[3].range, 13..18);
+        assert_eq!(wad.find_map("e1m2"), Some(maps[1].clone()));
+        assert_eq!(wad.find_map("PLAYPAL"), None);
+    }
+
+    #[test]
+    fn test_repeated_component_starts_new_map() {
+        let wad = wad_of(&["E1M1", "THINGS", "E1M2", "THINGS", "THINGS"]);
+        let maps = wad.maps();
+        assert_eq!(maps.len(), 2);
+        assert_eq!(maps[1].range, 2..4);
+    }
+}
@@ -0,0 +1,31 @@
+//! Level data: locating a map's lumps in the directory, decoding them and
+//! writing them back.
+
+mod blockmap;
+mod bsp;
+mod doom;
+mod extended;
+mod hexen;
+mod level;
+mod lint;
+mod lumps;
+mod reject;
+mod sight;
+#[cfg(test)]
+mod testutil;
+pub mod udmf;
+mod writer;
+
+pub use blockmap::{Blockmap, BlockmapCompression, BLOCK_SIZE};
+pub use bsp::NodeBuildError;
+pub use doom::{
+    BoundingBox, Linedef, Node, NodeChild, Sector, Seg, Sidedef, SubSector, Thing, Vertex,
+    NO_LINEDEF, NO_SIDEDEF,
+};
+pub use extended::{ExtendedNodes, FixedVertex, NodeDecodeError, NodeFormat};
+pub use hexen::{HexenLinedef, HexenThing};
+pub use level::{LineGeometry, Linedefs, Map, MapDecodeError, MapWarning, Things};
+pub use lint::{Diagnostic, LintOptions, MapObject, Problem, Severity};
+pub use lumps::{MapFormat, MapLumps};
+pub use reject::{Reject, RejectMode};
+pub use writer::MapEncodeError;
@@ -0,0 +1,211 @@
+//! REJECT table generation.
+
+use super::{sight, Map};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RejectMode {
+    /// All zeros: every sector may see every other one. Always correct, and
+    /// the fastest to build.
+    Empty,
+    /// An approximation by reachability: sectors with no path of two-sided
+    /// lines between them cannot see each other and are rejected. Connected
+    /// sectors are left visible even when walls hide them from each other,
+    /// so the table never hides a monster with a clear line of sight but
+    /// saves fewer sight checks than a real visibility pass.
+    Reachability,
+    /// A visibility pass over the map's BSP cells: sectors are rejected when
+    /// no straight line through the openings between them avoids the walls.
+    /// The slowest mode, since it builds nodes for the map and follows every
+    /// chain of openings; it falls back to reachability when no nodes can be
+    /// built.
+    LineOfSight,
+}
+
+/// One bit per sector pair; a set bit means the row sector cannot see the
+/// column sector.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Reject {
+    pub sectors: usize,
+    pub bits: Vec<u8>,
+}
+impl Reject {
+    pub fn empty(sectors: usize) -> Reject {
+        Reject {
+            sectors,
+            bits: vec![0; (sectors * sectors).div_ceil(8)],
+        }
+    }
+
+    /// Short lumps are padded with zeros, which is how engines treat them.
+    pub fn decode(bytes: &[u8], sectors: usize) -> Reject {
+        let mut reject = Reject::empty(sectors);
+        let len = reject.bits.len().min(bytes.len());
+        reject.bits[..len].copy_from_slice(&bytes[..len]);
+        reject
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        self.bits.clone()
+    }
+
+    pub fn is_rejected(&self, from: usize, to: usize) -> bool {
+        let bit = from * self.sectors + to;
+        self.bits
+            .get(bit / 8)
+            .is_some_and(|byte| byte & (1 << (bit % 8)) != 0)
+    }
+
+    pub fn set_rejected(&mut self, from: usize, to: usize, rejected: bool) {
+        let bit = from * self.sectors + to;
+        if rejected {
+            self.bits[bit / 8] |= 1 << (bit % 8);
+        } else {
+            self.bits[bit / 8] &= !(1 << (bit % 8));
+        }
+    }
+
+    pub fn build(map: &Map, mode: RejectMode) -> Reject {
+        let sectors = map.sectors.len();
+        let mut reject = Reject::empty(sectors);
+        if mode == RejectMode::Empty {
+            return reject;
+        }
+        if mode == RejectMode::LineOfSight {
+            if let Some(visible) = sight::visible_sectors(map) {
+                for (from, row) in visible.iter().enumerate() {
+                    for (to, &visible) in row.iter().enumerate() {
+                        reject.set_rejected(from, to, !visible);
+                    }
+                }
+                return reject;
+            }
+        }
+        let sector_of = |side: Option<u16>| {
+            side.and_then(|s| map.sidedefs.get(s as usize))
+                .map(|s| s.sector as usize)
+                .filter(|&s| s < sectors)
+        };
+        let mut group: Vec<usize> = (0..sectors).collect();
+        fn root(group: &mut [usize], mut s: usize) -> usize {
+            while group[s] != s {
+                group[s] = group[group[s]];
+                s = group[s];
+            }
+            s
+        }
+        for line in map.linedefs.geometry() {
+            if let (Some(a), Some(b)) = (sector_of(line.right), sector_of(line.left)) {
+                let (a, b) = (root(&mut group, a), root(&mut group, b));
+                group[a] = b;
+            }
+        }
+        let roots: Vec<usize> = (0..sectors).map(|s| root(&mut group, s)).collect();
+        for from in 0..sectors {
+            for to in 0..sectors {
+                if roots[from] != roots[to] {
+                    reject.set_rejected(from, to, true);
+                }
+            }
+        }
+        reject
+    }
+}
+
+impl Map {
+    pub fn build_reject(&mut self, mode: RejectMode) {
+        self.reject = Some(Reject::build(self, mode));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::map::testutil::{self, Sketch};
+
+    #[test]
+    fn test_reachability() {
+        let mut sketch = Sketch::default();
+        let a = sketch.sector();
+        let b = sketch.sector();
+        let c = sketch.sector();
+        sketch.polygon(&[(0, 0), (0, 64), (64, 64)], a);
+        sketch.line((64, 0), (64, 64), b, Some(c));
+        sketch.polygon(&[(200, 0), (200, 64), (264, 64)], c);
+        let map = sketch.finish();
+        let reject = Reject::build(&map, RejectMode::Reachability);
+        assert_eq!(reject.bits.len(), 2);
+        assert!(reject.is_rejected(0, 1) && reject.is_rejected(2, 0));
+        assert!(!reject.is_rejected(1, 2) && !reject.is_rejected(2, 1));
+        assert!(!reject.is_rejected(0, 0));
+        assert_eq!(Reject::decode(&reject.encode(), 3), reject);
+    }
+
+    /// a and c open onto the two arms of the L-shaped b, so no sightline
+    /// joins them, but both are reachable from b.
+    fn alcoves() -> (Map, usize, usize, usize) {
+        let mut sketch = Sketch::default();
+        let a = sketch.sector();
+        let b = sketch.sector();
+        let c = sketch.sector();
+        sketch.line((0, 0), (0, 128), b, None);
+        sketch.line((0, 128), (64, 128), b, Some(a));
+        sketch.line((64, 128), (64, 64), b, None);
+        sketch.line((64, 64), (128, 64), b, None);
+        sketch.line((128, 64), (128, 0), b, Some(c));
+        sketch.line((128, 0), (0, 0), b, None);
+        sketch.line((0, 128), (0, 192), a, None);
+        sketch.line((0, 192), (64, 192), a, None);
+        sketch.line((64, 192), (64, 128), a, None);
+        sketch.line((128, 64), (192, 64), c, None);
+        sketch.line((192, 64), (192, 0), c, None);
+        sketch.line((192, 0), (128, 0), c, None);
+        (sketch.finish(), a as usize, b as usize, c as usize)
+    }
+
+    #[test]
+    fn test_reachability_ignores_occlusion() {
+        let (map, a, _, c) = alcoves();
+        let reject = Reject::build(&map, RejectMode::Reachability);
+        assert!(!reject.is_rejected(a, c));
+        assert!(!reject.is_rejected(c, a));
+        assert_eq!(reject, Reject::empty(3));
+    }
+
+    #[test]
+    fn test_line_of_sight() {
+        let (map, a, b, c) = alcoves();
+        let reject = Reject::build(&map, RejectMode::LineOfSight);
+        assert!(reject.is_rejected(a, c) && reject.is_rejected(c, a));
+        assert!(!reject.is_rejected(a, b) && !reject.is_rejected(b, a));
+        assert!(!reject.is_rejected(b, c) && !reject.is_rejected(c, b));
+        assert!(!reject.is_rejected(a, a));
+
+        // A straight corridor of three sectors sees end to end, and
+        // unconnected sectors are rejected as with reachability.
+        let mut sketch = Sketch::default();
+        let sectors: Vec<u16> = (0..4).map(|_| sketch.sector()).collect();
+        for (i, &sector) in sectors[..3].iter().enumerate() {
+            let x = i as i16 * 64;
+            sketch.line((x, 0), (x, 64), sector, (i > 0).then(|| sectors[i - 1]));
+            sketch.line((x, 64), (x + 64, 64), sector, None);
+            sketch.line((x + 64, 0), (x, 0), sector, None);
+        }
+        sketch.line((192, 64), (192, 0), sectors[2], None);
+        sketch.polygon(&[(300, 0), (300, 64), (364, 64), (364, 0)], sectors[3]);
+        let map = sketch.finish();
+        let reject = Reject::build(&map, RejectMode::LineOfSight);
+        assert!(!reject.is_rejected(0, 2) && !reject.is_rejected(2, 0));
+        assert!(reject.is_rejected(0, 3) && reject.is_rejected(3, 1));
+    }
+
+    #[test]
+    fn test_empty() {
+        let mut map = testutil::two_rooms();
+        map.build_reject(RejectMode::Empty);
+        assert_eq!(map.reject, Some(Reject::empty(2)));
+        assert_eq!(
+            Reject::build(&map, RejectMode::Reachability),
+            Reject::empty(2)
+        );
+    }
+}
@@ -0,0 +1,462 @@
+//! Sector-to-sector visibility for REJECT, by portal flow between the convex
+//! cells of a BSP tree.
+//!
+//! Each subsector becomes a cell: the region its nodes leave it, trimmed by
+//! its own segs. Cells touch through portals, the stretches of shared edge
+//! no one-sided wall covers. A sightline from a cell crosses a chain of
+//! portals, and every portal it has crossed narrows the window it can pass
+//! through next, so a sector is visible from another only when some chain
+//! of windows between them stays open. The windows bound the lines through a
+//! chain from outside, and cells may take in some of the void behind walls,
+//! so the result can see more than the engine would; only gaps narrower than
+//! `EPSILON` are treated as closed.
+
+use std::collections::{HashMap, HashSet};
+
+use super::{Map, NodeChild, NO_LINEDEF};
+
+/// Distance under which points count as touching, and the length under
+/// which a window counts as closed.
+const EPSILON: f64 = 1.0 / 64.0;
+/// How far the outermost cells reach past the map's vertices.
+const MARGIN: f64 = 64.0;
+/// Side of the squares edges are bucketed into when looking for neighbours.
+const GRID: f64 = 256.0;
+
+type Point = (f64, f64);
+
+/// A stretch of portal, with the cell it leads into on the left of `a` to
+/// `b`.
+#[derive(Debug, Clone, Copy)]
+struct Window {
+    a: Point,
+    b: Point,
+}
+impl Window {
+    fn length(&self) -> f64 {
+        (self.b.0 - self.a.0).hypot(self.b.1 - self.a.1)
+    }
+
+    /// Signed distance of `p` from the window's line, positive on its far
+    /// side.
+    fn side_of(&self, p: Point) -> f64 {
+        side_of(self.a, self.b, p)
+    }
+
+    /// Where the window's ends fall along `line`, in units of its length.
+    fn span_along(&self, line: Window) -> (f64, f64) {
+        let (dx, dy) = (line.b.0 - line.a.0, line.b.1 - line.a.1);
+        let length = dx * dx + dy * dy;
+        let along = |p: Point| ((p.0 - line.a.0) * dx + (p.1 - line.a.1) * dy) / length;
+        let (a, b) = (along(self.a), along(self.b));
+        (a.min(b), a.max(b))
+    }
+
+    /// The part of the window where `distance` is not negative.
+    fn clip(self, distance: impl Fn(Point) -> f64) -> Option<Window> {
+        let (da, db) = (distance(self.a), distance(self.b));
+        let window = match (da >= 0.0, db >= 0.0) {
+            (true, true) => self,
+            (false, false) => return None,
+            (true, false) => Window {
+                a: self.a,
+                b: lerp(self.a, self.b, da / (da - db)),
+            },
+            (false, true) => Window {
+                a: lerp(self.a, self.b, da / (da - db)),
+                b: self.b,
+            },
+        };
+        (window.length() >= EPSILON).then_some(window)
+    }
+
+    /// Clips the window to the lines through an endpoint of `from` and one
+    /// of `pass` that keep `from` and `pass` on opposite sides, which bound
+    /// every line crossing both.
+    fn clip_to_separators(self, from: Window, pass: Window) -> Option<Window> {
+        let mut window = self;
+        for (s, s_other) in [(from.a, from.b), (from.b, from.a)] {
+            for (p, p_other) in [(pass.a, pass.b), (pass.b, pass.a)] {
+                if (p.0 - s.0).hypot(p.1 - s.1) < EPSILON {
+                    continue;
+                }
+                let (ds, dp) = (side_of(s, p, s_other), side_of(s, p, p_other));
+                if ds.abs() < EPSILON || dp.abs() < EPSILON || (ds > 0.0) == (dp > 0.0) {
+                    continue;
+                }
+                let sign = dp.signum();
+                window = window.clip(|x| side_of(s, p, x) * sign)?;
+            }
+        }
+        Some(window)
+    }
+}
+
+/// Signed distance of `p` from the line through `a` and `b`, positive on its
+/// left. Doom's front (right) side is negative.
+fn side_of(a: Point, b: Point, p: Point) -> f64 {
+    let (dx, dy) = (b.0 - a.0, b.1 - a.1);
+    (dx * (p.1 - a.1) - dy * (p.0 - a.0)) / dx.hypot(dy)
+}
+
+fn lerp(a: Point, b: Point, t: f64) -> Point {
+    (a.0 + (b.0 - a.0) * t, a.1 + (b.1 - a.1) * t)
+}
+
+/// The part of the convex `polygon` where `distance` is not negative.
+fn clip_polygon(polygon: &[Point], distance: impl Fn(Point) -> f64) -> Vec<Point> {
+    let mut out = vec![];
+    for (i, &p) in polygon.iter().enumerate() {
+        let q = polygon[(i + 1) % polygon.len()];
+        let (dp, dq) = (distance(p), distance(q));
+        if dp >= 0.0 {
+            out.push(p);
+        }
+        if (dp > 0.0 && dq < 0.0) || (dp < 0.0 && dq > 0.0) {
+            out.push(lerp(p, q, dp / (dp - dq)));
+        }
+    }
+    out
+}
+
+struct Cell {
+    sector: Option<usize>,
+    polygon: Vec<Point>,
+    /// Indices into `Cells::portals` of the portals out of this cell.
+    portals: Vec<usize>,
+}
+
+struct Portal {
+    window: Window,
+    to: usize,
+}
+
+struct Cells {
+    cells: Vec<Cell>,
+    portals: Vec<Portal>,
+}
+impl Cells {
+    /// `map` must have nodes.
+    fn new(map: &Map) -> Cells {
+        let point = |v: u32| {
+            map.vertexes
+                .get(v as usize)
+                .map_or((0.0, 0.0), |v| (v.x as f64, v.y as f64))
+        };
+        let (mut left, mut right, mut bottom, mut top) = (0.0, 0.0, 0.0, 0.0);
+        for (i, v) in map.vertexes.iter().enumerate() {
+            let (x, y) = (v.x as f64, v.y as f64);
+            if i == 0 {
+                (left, right, bottom, top) = (x, x, y, y);
+            }
+            (left, right) = (f64::min(left, x), f64::max(right, x));
+            (bottom, top) = (f64::min(bottom, y), f64::max(top, y));
+        }
+        let (left, right) = (left - MARGIN, right + MARGIN);
+        let (bottom, top) = (bottom - MARGIN, top + MARGIN);
+        let bounds = vec![(left, bottom), (left, top), (right, top), (right, bottom)];
+
+        let lines = map.linedefs.geometry();
+        let mut cells: Vec<Cell> = (0..map.subsectors.len())
+            .map(|_| Cell {
+                sector: None,
+                polygon: vec![],
+                portals: vec![],
+            })
+            .collect();
+        let root = match map.nodes.len() {
+            0 => NodeChild::SubSector(0),
+            n => NodeChild::Node(n as u32 - 1),
+        };
+        let mut pending = vec![(root, bounds)];
+        while let Some((child, polygon)) = pending.pop() {
+            match child {
+                NodeChild::Node(index) => {
+                    let Some(node) = map.nodes.get(index as usize) else {
+                        continue;
+                    };
+                    let a = (node.x as f64, node.y as f64);
+                    let b = (a.0 + node.dx as f64, a.1 + node.dy as f64);
+                    if a == b {
+                        continue;
+                    }
+                    let front = clip_polygon(&polygon, |p| -side_of(a, b, p));
+                    let back = clip_polygon(&polygon, |p| side_of(a, b, p));
+                    pending.push((node.right, front));
+                    pending.push((node.left, back));
+                }
+                NodeChild::SubSector(index) => {
+                    let Some(subsector) = map.subsectors.get(index as usize) else {
+                        continue;
+                    };
+                    let first = subsector.first_seg as usize;
+                    let segs = map
+                        .segs
+                        .get(first..first + subsector.seg_count as usize)
+                        .unwrap_or_default();
+                    let mut polygon = polygon;
+                    for seg in segs {
+                        let (a, b) = (point(seg.v1), point(seg.v2));
+                        if a != b {
+                            polygon = clip_polygon(&polygon, |p| -side_of(a, b, p));
+                        }
+                    }
+                    let sector = segs.iter().find_map(|seg| {
+                        let line = lines.get(seg.linedef as usize)?;
+                        let side = if seg.direction == 0 {
+                            line.right
+                        } else {
+                            line.left
+                        };
+                        let sector = map.sidedefs.get(side? as usize)?.sector as usize;
+                        (sector < map.sectors.len()).then_some(sector)
+                    });
+                    cells[index as usize] = Cell {
+                        sector,
+                        polygon,
+                        portals: vec![],
+                    };
+                }
+            }
+        }
+
+        let mut walls = vec![];
+        for seg in &map.segs {
+            let one_sided = seg.linedef != NO_LINEDEF
+                && lines
+                    .get(seg.linedef as usize)
+                    .is_some_and(|line| line.right.is_none() || line.left.is_none());
+            if one_sided {
+                walls.push((point(seg.v1), point(seg.v2)));
+            }
+        }
+        let mut cells = Cells {
+            cells,
+            portals: vec![],
+        };
+        cells.connect(&walls);
+        cells
+    }
+
+    /// Finds the portals between cells: overlapping stretches of collinear,
+    /// opposite edges, less the parts walls cover.
+    fn connect(&mut self, walls: &[(Point, Point)]) {
+        let mut edges = vec![];
+        for (index, cell) in self.cells.iter().enumerate() {
+            let polygon = &cell.polygon;
+            for (i, &a) in polygon.iter().enumerate() {
+                let b = polygon[(i + 1) % polygon.len()];
+                if (b.0 - a.0).hypot(b.1 - a.1) >= EPSILON {
+                    edges.push((index, a, b));
+                }
+            }
+        }
+        let mut grid: HashMap<(i64, i64), (Vec<usize>, Vec<usize>)> = HashMap::new();
+        for (i, &(_, a, b)) in edges.iter().enumerate() {
+            for key in squares(a, b) {
+                grid.entry(key).or_default().0.push(i);
+            }
+        }
+        for (i, &(a, b)) in walls.iter().enumerate() {
+            for key in squares(a, b) {
+                grid.entry(key).or_default().1.push(i);
+            }
+        }
+        let mut pairs = HashSet::new();
+        for (bucket, _) in grid.values() {
+            for (n, &i) in bucket.iter().enumerate() {
+                for &j in &bucket[n + 1..] {
+                    if edges[i].0 != edges[j].0 {
+                        pairs.insert((i.min(j), i.max(j)));
+                    }
+                }
+            }
+        }
+        let mut pairs: Vec<(usize, usize)> = pairs.into_iter().collect();
+        pairs.sort_unstable();
+        for (i, j) in pairs {
+            let (from, a, b) = edges[i];
+            let (to, c, d) = edges[j];
+            let length = (b.0 - a.0).hypot(b.1 - a.1);
+            if side_of(a, b, c).abs() > EPSILON || side_of(a, b, d).abs() > EPSILON {
+                continue;
+            }
+            let along = |p: Point| ((p.0 - a.0) * (b.0 - a.0) + (p.1 - a.1) * (b.1 - a.1)) / length;
+            let (tc, td) = (along(c), along(d));
+            if tc < td {
+                continue;
+            }
+            let mut open = vec![(td.max(0.0), tc.min(length))];
+            let nearby: HashSet<usize> = squares(a, b)
+                .filter_map(|key| grid.get(&key))
+                .flat_map(|(_, walls)| walls.iter().copied())
+                .collect();
+            for wall in nearby {
+                let (p, q) = walls[wall];
+                if side_of(a, b, p).abs() > EPSILON || side_of(a, b, q).abs() > EPSILON {
+                    continue;
+                }
+                let (tp, tq) = (along(p), along(q));
+                let (lo, hi) = (tp.min(tq), tp.max(tq));
+                open = open
+                    .into_iter()
+                    .flat_map(|(start, end)| [(start, end.min(lo)), (start.max(hi), end)])
+                    .collect();
+                open.retain(|&(start, end)| end - start >= EPSILON);
+            }
+            for (start, end) in open {
+                if end - start < EPSILON {
+                    continue;
+                }
+                let (p, q) = (lerp(a, b, start / length), lerp(a, b, end / length));
+                self.add_portal(from, to, Window { a: p, b: q });
+                self.add_portal(to, from, Window { a: q, b: p });
+            }
+        }
+    }
+
+    fn add_portal(&mut self, from: usize, to: usize, window: Window) {
+        let polygon = &self.cells[from].polygon;
+        let count = polygon.len() as f64;
+        let centre = polygon
+            .iter()
+            .fold((0.0, 0.0), |c, p| (c.0 + p.0 / count, c.1 + p.1 / count));
+        let window = if window.side_of(centre) > 0.0 {
+            Window {
+                a: window.b,
+                b: window.a,
+            }
+        } else {
+            window
+        };
+        self.cells[from].portals.push(self.portals.len());
+        self.portals.push(Portal { window, to });
+    }
+
+    /// The sectors visible from `start`, by flowing through every chain of
+    /// portals that leaves a window open. A chain that reaches a portal with
+    /// windows inside those of one already followed through it is dropped,
+    /// since it can only see less.
+    fn flow(&self, start: usize, seen: &mut [bool]) {
+        struct Frame {
+            cell: usize,
+            source: Window,
+            pass: Option<Window>,
+            next: usize,
+        }
+        let mut on_stack = vec![false; self.cells.len()];
+        on_stack[start] = true;
+        let mut followed: Vec<Vec<[(f64, f64); 2]>> = vec![vec![]; self.portals.len()];
+        for &first in &self.cells[start].portals {
+            followed.iter_mut().for_each(Vec::clear);
+            let portal = &self.portals[first];
+            let origin = portal.window;
+            if let Some(sector) = self.cells[portal.to].sector {
+                seen[sector] = true;
+            }
+            on_stack[portal.to] = true;
+            let mut stack = vec![Frame {
+                cell: portal.to,
+                source: portal.window,
+                pass: None,
+                next: 0,
+            }];
+            while let Some(frame) = stack.last_mut() {
+                let Some(&index) = self.cells[frame.cell].portals.get(frame.next) else {
+                    on_stack[frame.cell] = false;
+                    stack.pop();
+                    continue;
+                };
+                frame.next += 1;
+                let portal = &self.portals[index];
+                if on_stack[portal.to] {
+                    continue;
+                }
+                let (source, pass) = (frame.source, frame.pass);
+                let target = portal.window.clip(|p| source.side_of(p));
+                let target = match pass {
+                    Some(pass) => target
+                        .and_then(|t| t.clip(|p| pass.side_of(p)))
+                        .and_then(|t| t.clip_to_separators(source, pass)),
+                    None => target,
+                };
+                let Some(target) = target else {
+                    continue;
+                };
+                let source = match pass {
+                    Some(pass) => match source.clip_to_separators(target, pass) {
+                        Some(source) => source,
+                        None => continue,
+                    },
+                    None => source,
+                };
+                let spans = [source.span_along(origin), target.span_along(portal.window)];
+                let inside = |(lo, hi): (f64, f64), (outer_lo, outer_hi): (f64, f64)| {
+                    lo >= outer_lo - 1e-9 && hi <= outer_hi + 1e-9
+                };
+                if followed[index]
+                    .iter()
+                    .any(|f| inside(spans[0], f[0]) && inside(spans[1], f[1]))
+                {
+                    continue;
+                }
+                followed[index].push(spans);
+                if let Some(sector) = self.cells[portal.to].sector {
+                    seen[sector] = true;
+                }
+                on_stack[portal.to] = true;
+                stack.push(Frame {
+                    cell: portal.to,
+                    source,
+                    pass: Some(target),
+                    next: 0,
+                });
+            }
+        }
+    }
+}
+
+/// The grid squares the segment from `a` to `b` may touch.
+fn squares(a: Point, b: Point) -> impl Iterator<Item = (i64, i64)> {
+    let square = |v: f64| (v / GRID).floor() as i64;
+    let (x0, x1) = (
+        square(a.0.min(b.0) - EPSILON),
+        square(a.0.max(b.0) + EPSILON),
+    );
+    let (y0, y1) = (
+        square(a.1.min(b.1) - EPSILON),
+        square(a.1.max(b.1) + EPSILON),
+    );
+    (x0..=x1).flat_map(move |x| (y0..=y1).map(move |y| (x, y)))
+}
+
+/// For each sector, which sectors it can see, or `None` when no nodes could
+/// be built. Sectors no subsector belongs to see, and are seen by, all.
+pub(super) fn visible_sectors(map: &Map) -> Option<Vec<Vec<bool>>> {
+    let mut built = map.clone();
+    built.build_nodes().ok()?;
+    let cells = Cells::new(&built);
+    let sectors = map.sectors.len();
+    let mut visible = vec![vec![false; sectors]; sectors];
+    for (index, cell) in cells.cells.iter().enumerate() {
+        let Some(sector) = cell.sector else {
+            continue;
+        };
+        let mut seen = vec![false; sectors];
+        seen[sector] = true;
+        cells.flow(index, &mut seen);
+        for (other, &seen) in seen.iter().enumerate() {
+            if seen {
+                visible[sector][other] = true;
+                visible[other][sector] = true;
+            }
+        }
+    }
+    let placed: HashSet<usize> = cells.cells.iter().filter_map(|c| c.sector).collect();
+    for (sector, row) in visible.iter_mut().enumerate() {
+        for (other, visible) in row.iter_mut().enumerate() {
+            *visible |= !placed.contains(&sector) || !placed.contains(&other);
+        }
+    }
+    Some(visible)
+}
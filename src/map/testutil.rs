@@ -0,0 +1,144 @@
+//! Small hand-drawn maps shared by the map tests.
+
+use std::collections::HashMap;
+
+use crate::LumpName;
+
+use super::{Linedef, Linedefs, Map, MapFormat, Sector, Sidedef, Thing, Things, Vertex};
+
+#[derive(Default)]
+pub(crate) struct Sketch {
+    vertexes: Vec<Vertex>,
+    lookup: HashMap<(i16, i16), u16>,
+    linedefs: Vec<Linedef>,
+    sidedefs: Vec<Sidedef>,
+    sectors: Vec<Sector>,
+    things: Vec<Thing>,
+}
+impl Sketch {
+    pub fn sector(&mut self) -> u16 {
+        self.sectors.push(Sector {
+            floor_height: 0,
+            ceiling_height: 128,
+            floor_texture: LumpName::known("FLOOR4_8"),
+            ceiling_texture: LumpName::known("CEIL3_5"),
+            light: 160,
+            special: 0,
+            tag: 0,
+        });
+        (self.sectors.len() - 1) as u16
+    }
+
+    fn vertex(&mut self, (x, y): (i16, i16)) -> u16 {
+        let vertexes = &mut self.vertexes;
+        *self.lookup.entry((x, y)).or_insert_with(|| {
+            vertexes.push(Vertex { x, y });
+            (vertexes.len() - 1) as u16
+        })
+    }
+
+    fn side(&mut self, sector: u16, middle: &str) -> u16 {
+        self.sidedefs.push(Sidedef {
+            x_offset: 0,
+            y_offset: 0,
+            upper: LumpName::known("-"),
+            lower: LumpName::known("-"),
+            middle: LumpName::known(middle),
+            sector,
+        });
+        (self.sidedefs.len() - 1) as u16
+    }
+
+    pub fn line(&mut self, a: (i16, i16), b: (i16, i16), right: u16, left: Option<u16>) {
+        let v1 = self.vertex(a);
+        let v2 = self.vertex(b);
+        let two_sided = left.is_some();
+        let middle = if two_sided { "-" } else { "STARTAN2" };
+        let right = Some(self.side(right, middle));
+        let left = left.map(|sector| self.side(sector, middle));
+        self.linedefs.push(Linedef {
+            v1,
+            v2,
+            flags: if two_sided { 4 } else { 1 },
+            special: 0,
+            tag: 0,
+            right,
+            left,
+        });
+    }
+
+    /// One-sided walls around `points`, which must run clockwise so the
+    /// sector is on their right.
+    pub fn polygon(&mut self, points: &[(i16, i16)], sector: u16) {
+        for (i, &a) in points.iter().enumerate() {
+            self.line(a, points[(i + 1) % points.len()], sector, None);
+        }
+    }
+
+    pub fn thing(&mut self, x: i16, y: i16, kind: i16) {
+        self.things.push(Thing {
+            x,
+            y,
+            angle: 0,
+            kind,
+            flags: 7,
+        });
+    }
+
+    pub fn finish(self) -> Map {
+        Map {
+            name: LumpName::known("MAP01"),
+            format: MapFormat::Doom,
+            things: Things::Doom(self.things),
+            linedefs: Linedefs::Doom(self.linedefs),
+            sidedefs: self.sidedefs,
+            vertexes: self.vertexes,
+            segs: vec![],
+            subsectors: vec![],
+            nodes: vec![],
+            sectors: self.sectors,
+            reject: None,
+            blockmap: None,
+            behavior: None,
+            warnings: vec![],
+        }
+    }
+}
+
+pub(crate) fn l_shaped_room() -> Map {
+    let mut sketch = Sketch::default();
+    let sector = sketch.sector();
+    sketch.polygon(
+        &[(0, 0), (0, 128), (64, 128), (64, 64), (128, 64), (128, 0)],
+        sector,
+    );
+    sketch.thing(32, 32, 1);
+    sketch.finish()
+}
+
+/// Two 64x64 rooms joined by a two-sided line at x = 64; sector 0 is on the
+/// left.
+pub(crate) fn two_rooms() -> Map {
+    let mut sketch = Sketch::default();
+    let a = sketch.sector();
+    let b = sketch.sector();
+    sketch.line((0, 0), (0, 64), a, None);
+    sketch.line((0, 64), (64, 64), a, None);
+    sketch.line((64, 64), (64, 0), a, Some(b));
+    sketch.line((64, 0), (0, 0), a, None);
+    sketch.line((64, 64), (128, 64), b, None);
+    sketch.line((128, 64), (128, 0), b, None);
+    sketch.line((128, 0), (64, 0), b, None);
+    sketch.thing(32, 32, 1);
+    sketch.finish()
+}
+
+/// A square room around a diamond-shaped pillar, which forces splits.
+pub(crate) fn room_with_pillar() -> Map {
+    let mut sketch = Sketch::default();
+    let sector = sketch.sector();
+    sketch.polygon(&[(0, 0), (0, 256), (256, 256), (256, 0)], sector);
+    sketch.polygon(&[(128, 80), (176, 128), (128, 176), (80, 128)], sector);
+    sketch.thing(32, 32, 1);
+    sketch.finish()
+}
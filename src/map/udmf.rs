@@ -0,0 +1,807 @@
+//! UDMF `TEXTMAP` lexing, parsing into a typed model, and serialization.
+//!
+//! Keys the model knows are decoded into typed fields; every other key,
+//! including the many boolean flags ports define, is kept in `extra` in its
+//! original order so a parse/serialize round trip loses nothing.
+
+use std::fmt::Write;
+
+use crate::{Lump, LumpName, Wad};
+
+use super::{MapDecodeError, MapFormat};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Int(i64),
+    Float(f64),
+    Bool(bool),
+    Str(String),
+    /// A bare identifier other than `true`/`false`.
+    Keyword(String),
+}
+impl Value {
+    fn as_int(&self) -> Option<i64> {
+        match self {
+            Value::Int(value) => Some(*value),
+            _ => None,
+        }
+    }
+
+    fn as_float(&self) -> Option<f64> {
+        match self {
+            Value::Int(value) => Some(*value as f64),
+            Value::Float(value) => Some(*value),
+            _ => None,
+        }
+    }
+
+    fn as_str(&self) -> Option<&str> {
+        match self {
+            Value::Str(value) => Some(value),
+            _ => None,
+        }
+    }
+}
+impl std::fmt::Display for Value {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Value::Int(value) => write!(f, "{}", value),
+            Value::Float(value) => {
+                let text = value.to_string();
+                if text.contains('.') || !value.is_finite() {
+                    f.write_str(&text)
+                } else {
+                    write!(f, "{}.0", text)
+                }
+            }
+            Value::Bool(value) => write!(f, "{}", value),
+            Value::Str(value) => {
+                f.write_char('"')?;
+                for c in value.chars() {
+                    if c == '"' || c == '\\' {
+                        f.write_char('\\')?;
+                    }
+                    f.write_char(c)?;
+                }
+                f.write_char('"')
+            }
+            Value::Keyword(value) => f.write_str(value),
+        }
+    }
+}
+
+/// Key/value pairs in source order. Keys are stored lowercased, as UDMF keys
+/// are case-insensitive.
+pub type Fields = Vec<(String, Value)>;
+
+#[derive(Debug)]
+pub enum UdmfError {
+    UnexpectedCharacter {
+        line: usize,
+        found: char,
+    },
+    UnterminatedString {
+        line: usize,
+    },
+    UnterminatedComment {
+        line: usize,
+    },
+    InvalidNumber {
+        line: usize,
+        text: String,
+    },
+    UnexpectedToken {
+        line: usize,
+        expected: &'static str,
+    },
+    UnexpectedEnd {
+        expected: &'static str,
+    },
+    MissingField {
+        block: &'static str,
+        field: &'static str,
+    },
+    WrongType {
+        line: usize,
+        key: String,
+    },
+    InvalidUtf8,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Identifier(String),
+    Value(Value),
+    OpenBrace,
+    CloseBrace,
+    Equals,
+    Semicolon,
+}
+
+fn tokenize(source: &str) -> Result<Vec<(Token, usize)>, UdmfError> {
+    let mut tokens = vec![];
+    let mut chars = source.chars().peekable();
+    let mut line = 1;
+    while let Some(c) = chars.next() {
+        match c {
+            '\n' => line += 1,
+            c if c.is_whitespace() => {}
+            '/' if chars.peek() == Some(&'/') => while chars.next_if(|&c| c != '\n').is_some() {},
+            '/' if chars.peek() == Some(&'*') => {
+                chars.next();
+                let start = line;
+                let mut previous = '\0';
+                loop {
+                    match chars.next() {
+                        Some('/') if previous == '*' => break,
+                        Some(c) => {
+                            if c == '\n' {
+                                line += 1;
+                            }
+                            previous = c;
+                        }
+                        None => return Err(UdmfError::UnterminatedComment { line: start }),
+                    }
+                }
+            }
+            '{' => tokens.push((Token::OpenBrace, line)),
+            '}' => tokens.push((Token::CloseBrace, line)),
+            '=' => tokens.push((Token::Equals, line)),
+            ';' => tokens.push((Token::Semicolon, line)),
+            '"' => {
+                let start = line;
+                let mut text = String::new();
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => match chars.next() {
+                            Some(c) => text.push(c),
+                            None => return Err(UdmfError::UnterminatedString { line: start }),
+                        },
+                        Some(c) => {
+                            if c == '\n' {
+                                line += 1;
+                            }
+                            text.push(c);
+                        }
+                        None => return Err(UdmfError::UnterminatedString { line: start }),
+                    }
+                }
+                tokens.push((Token::Value(Value::Str(text)), start));
+            }
+            c if c.is_ascii_alphabetic() || c == '_' => {
+                let mut text = String::from(c);
+                while let Some(c) = chars.next_if(|c| c.is_ascii_alphanumeric() || *c == '_') {
+                    text.push(c);
+                }
+                let token = match text.to_ascii_lowercase().as_str() {
+                    "true" => Token::Value(Value::Bool(true)),
+                    "false" => Token::Value(Value::Bool(false)),
+                    _ => Token::Identifier(text),
+                };
+                tokens.push((token, line));
+            }
+            c if c.is_ascii_digit() || c == '+' || c == '-' || c == '.' => {
+                let mut text = String::from(c);
+                while let Some(c) = chars
+                    .next_if(|c| c.is_ascii_alphanumeric() || *c == '.' || *c == '+' || *c == '-')
+                {
+                    let exponent_sign =
+                        (c == '+' || c == '-') && !text.to_ascii_lowercase().ends_with('e');
+                    if exponent_sign {
+                        return Err(UdmfError::InvalidNumber { line, text });
+                    }
+                    text.push(c);
+                }
+                tokens.push((Token::Value(parse_number(&text, line)?), line));
+            }
+            found => return Err(UdmfError::UnexpectedCharacter { line, found }),
+        }
+    }
+    Ok(tokens)
+}
+
+fn parse_number(text: &str, line: usize) -> Result<Value, UdmfError> {
+    let invalid = || UdmfError::InvalidNumber {
+        line,
+        text: text.into(),
+    };
+    let (negative, digits) = match text.as_bytes().first() {
+        Some(b'-') => (true, &text[1..]),
+        Some(b'+') => (false, &text[1..]),
+        _ => (false, text),
+    };
+    let lower = digits.to_ascii_lowercase();
+    let magnitude = if let Some(hex) = lower.strip_prefix("0x") {
+        i64::from_str_radix(hex, 16).map_err(|_| invalid())?
+    } else if lower.contains(['.', 'e']) {
+        return text.parse().map(Value::Float).map_err(|_| invalid());
+    } else if lower.len() > 1 && lower.starts_with('0') {
+        i64::from_str_radix(&lower[1..], 8).map_err(|_| invalid())?
+    } else {
+        lower.parse().map_err(|_| invalid())?
+    };
+    Ok(Value::Int(if negative { -magnitude } else { magnitude }))
+}
+
+/// A block or assignment at the top level of a TEXTMAP.
+enum Global {
+    Assignment(String, Value, usize),
+    Block(String, Fields, usize),
+}
+
+fn parse_globals(source: &str) -> Result<Vec<Global>, UdmfError> {
+    let tokens = tokenize(source)?;
+    let mut tokens = tokens.into_iter().peekable();
+    let mut globals = vec![];
+    let expect = |token: Option<(Token, usize)>, wanted: Token, expected: &'static str| match token
+    {
+        Some((token, _)) if token == wanted => Ok(()),
+        Some((_, line)) => Err(UdmfError::UnexpectedToken { line, expected }),
+        None => Err(UdmfError::UnexpectedEnd { expected }),
+    };
+    while let Some((token, line)) = tokens.next() {
+        let Token::Identifier(name) = token else {
+            return Err(UdmfError::UnexpectedToken {
+                line,
+                expected: "identifier",
+            });
+        };
+        let name = name.to_ascii_lowercase();
+        match tokens.next() {
+            Some((Token::Equals, _)) => {
+                let value = parse_value(tokens.next())?;
+                expect(tokens.next(), Token::Semicolon, "';'")?;
+                globals.push(Global::Assignment(name, value, line));
+            }
+            Some((Token::OpenBrace, _)) => {
+                let mut fields = Fields::new();
+                loop {
+                    match tokens.next() {
+                        Some((Token::CloseBrace, _)) => break,
+                        Some((Token::Identifier(key), _)) => {
+                            expect(tokens.next(), Token::Equals, "'='")?;
+                            let value = parse_value(tokens.next())?;
+                            expect(tokens.next(), Token::Semicolon, "';'")?;
+                            fields.push((key.to_ascii_lowercase(), value));
+                        }
+                        Some((_, line)) => {
+                            return Err(UdmfError::UnexpectedToken {
+                                line,
+                                expected: "key or '}'",
+                            })
+                        }
+                        None => return Err(UdmfError::UnexpectedEnd { expected: "'}'" }),
+                    }
+                }
+                globals.push(Global::Block(name, fields, line));
+            }
+            Some((_, line)) => {
+                return Err(UdmfError::UnexpectedToken {
+                    line,
+                    expected: "'=' or '{'",
+                })
+            }
+            None => {
+                return Err(UdmfError::UnexpectedEnd {
+                    expected: "'=' or '{'",
+                })
+            }
+        }
+    }
+    Ok(globals)
+}
+
+fn parse_value(token: Option<(Token, usize)>) -> Result<Value, UdmfError> {
+    match token {
+        Some((Token::Value(value), _)) => Ok(value),
+        Some((Token::Identifier(keyword), _)) => Ok(Value::Keyword(keyword)),
+        Some((_, line)) => Err(UdmfError::UnexpectedToken {
+            line,
+            expected: "value",
+        }),
+        None => Err(UdmfError::UnexpectedEnd { expected: "value" }),
+    }
+}
+
+/// Pulls typed fields out of a block, leaving the rest as `extra`.
+struct FieldReader {
+    block: &'static str,
+    line: usize,
+    fields: Fields,
+}
+impl FieldReader {
+    fn take(&mut self, key: &str) -> Option<Value> {
+        let position = self.fields.iter().rposition(|(k, _)| k == key)?;
+        let value = self.fields.remove(position).1;
+        self.fields.retain(|(k, _)| k != key);
+        Some(value)
+    }
+
+    fn typed<T>(
+        &mut self,
+        key: &'static str,
+        convert: impl Fn(&Value) -> Option<T>,
+    ) -> Result<Option<T>, UdmfError> {
+        match self.take(key) {
+            Some(value) => convert(&value).map(Some).ok_or(UdmfError::WrongType {
+                line: self.line,
+                key: key.into(),
+            }),
+            None => Ok(None),
+        }
+    }
+
+    fn int(&mut self, key: &'static str, default: i64) -> Result<i64, UdmfError> {
+        Ok(self.typed(key, Value::as_int)?.unwrap_or(default))
+    }
+
+    fn float(&mut self, key: &'static str, default: f64) -> Result<f64, UdmfError> {
+        Ok(self.typed(key, Value::as_float)?.unwrap_or(default))
+    }
+
+    fn string(&mut self, key: &'static str, default: &str) -> Result<String, UdmfError> {
+        Ok(self
+            .typed(key, |v| v.as_str().map(String::from))?
+            .unwrap_or_else(|| default.into()))
+    }
+
+    fn required<T>(
+        &mut self,
+        key: &'static str,
+        convert: impl Fn(&Value) -> Option<T>,
+    ) -> Result<T, UdmfError> {
+        self.typed(key, convert)?.ok_or(UdmfError::MissingField {
+            block: self.block,
+            field: key,
+        })
+    }
+
+    fn args(&mut self) -> Result<[i64; 5], UdmfError> {
+        Ok([
+            self.int("arg0", 0)?,
+            self.int("arg1", 0)?,
+            self.int("arg2", 0)?,
+            self.int("arg3", 0)?,
+            self.int("arg4", 0)?,
+        ])
+    }
+}
+
+/// Accumulates a block's text, skipping optional keys at their default.
+struct FieldWriter<'a> {
+    out: &'a mut String,
+}
+impl FieldWriter<'_> {
+    fn value(&mut self, key: &str, value: Value) {
+        let _ = writeln!(self.out, "{} = {};", key, value);
+    }
+
+    fn int(&mut self, key: &str, value: i64, default: Option<i64>) {
+        if Some(value) != default {
+            self.value(key, Value::Int(value));
+        }
+    }
+
+    fn float(&mut self, key: &str, value: f64, default: Option<f64>) {
+        if Some(value) != default {
+            self.value(key, Value::Float(value));
+        }
+    }
+
+    fn string(&mut self, key: &str, value: &str, default: Option<&str>) {
+        if Some(value) != default {
+            self.value(key, Value::Str(value.into()));
+        }
+    }
+
+    fn args(&mut self, args: &[i64; 5]) {
+        for (i, &arg) in args.iter().enumerate() {
+            self.int(&format!("arg{}", i), arg, Some(0));
+        }
+    }
+
+    fn extra(&mut self, extra: &Fields) {
+        for (key, value) in extra {
+            self.value(key, value.clone());
+        }
+    }
+}
+
+fn flag(extra: &Fields, key: &str) -> bool {
+    extra
+        .iter()
+        .rev()
+        .find(|(k, _)| k.eq_ignore_ascii_case(key))
+        .is_some_and(|(_, v)| *v == Value::Bool(true))
+}
+
+fn set_flag(extra: &mut Fields, key: &str, value: bool) {
+    let key = key.to_ascii_lowercase();
+    extra.retain(|(k, _)| *k != key);
+    if value {
+        extra.push((key, Value::Bool(true)));
+    }
+}
+
+macro_rules! flag_accessors {
+    ($($ty:ty),*) => {$(
+        impl $ty {
+            /// Boolean flags live in `extra`; absent means false.
+            pub fn flag(&self, key: &str) -> bool {
+                flag(&self.extra, key)
+            }
+
+            pub fn set_flag(&mut self, key: &str, value: bool) {
+                set_flag(&mut self.extra, key, value)
+            }
+        }
+    )*};
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct UdmfThing {
+    pub id: i64,
+    pub x: f64,
+    pub y: f64,
+    pub height: f64,
+    pub angle: i64,
+    pub kind: i64,
+    pub special: i64,
+    pub args: [i64; 5],
+    pub extra: Fields,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct UdmfVertex {
+    pub x: f64,
+    pub y: f64,
+    pub extra: Fields,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct UdmfLinedef {
+    pub id: i64,
+    pub v1: i64,
+    pub v2: i64,
+    pub special: i64,
+    pub args: [i64; 5],
+    pub sidefront: i64,
+    /// -1 for one-sided lines.
+    pub sideback: i64,
+    pub extra: Fields,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct UdmfSidedef {
+    pub offsetx: i64,
+    pub offsety: i64,
+    pub texturetop: String,
+    pub texturebottom: String,
+    pub texturemiddle: String,
+    pub sector: i64,
+    pub extra: Fields,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct UdmfSector {
+    pub heightfloor: i64,
+    pub heightceiling: i64,
+    pub texturefloor: String,
+    pub textureceiling: String,
+    pub lightlevel: i64,
+    pub special: i64,
+    pub id: i64,
+    pub extra: Fields,
+}
+
+flag_accessors!(UdmfThing, UdmfVertex, UdmfLinedef, UdmfSidedef, UdmfSector);
+
+/// A block whose type the model does not know.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Block {
+    pub kind: String,
+    pub fields: Fields,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TextMap {
+    pub namespace: String,
+    /// Global assignments other than `namespace`.
+    pub globals: Fields,
+    pub things: Vec<UdmfThing>,
+    pub vertices: Vec<UdmfVertex>,
+    pub linedefs: Vec<UdmfLinedef>,
+    pub sidedefs: Vec<UdmfSidedef>,
+    pub sectors: Vec<UdmfSector>,
+    pub blocks: Vec<Block>,
+}
+impl TextMap {
+    pub fn new(namespace: &str) -> TextMap {
+        TextMap {
+            namespace: namespace.into(),
+            globals: vec![],
+            things: vec![],
+            vertices: vec![],
+            linedefs: vec![],
+            sidedefs: vec![],
+            sectors: vec![],
+            blocks: vec![],
+        }
+    }
+
+    pub fn parse(source: &str) -> Result<TextMap, UdmfError> {
+        let mut map = TextMap::new("");
+        for global in parse_globals(source)? {
+            let (kind, fields, line) = match global {
+                Global::Assignment(key, value, line) if key == "namespace" => {
+                    map.namespace = match value {
+                        Value::Str(namespace) => namespace,
+                        _ => return Err(UdmfError::WrongType { line, key }),
+                    };
+                    continue;
+                }
+                Global::Assignment(key, value, _) => {
+                    map.globals.push((key, value));
+                    continue;
+                }
+                Global::Block(kind, fields, line) => (kind, fields, line),
+            };
+            let block = match kind.as_str() {
+                "thing" => "thing",
+                "vertex" => "vertex",
+                "linedef" => "linedef",
+                "sidedef" => "sidedef",
+                "sector" => "sector",
+                _ => {
+                    map.blocks.push(Block { kind, fields });
+                    continue;
+                }
+            };
+            let mut r = FieldReader {
+                block,
+                line,
+                fields,
+            };
+            match block {
+                "thing" => map.things.push(UdmfThing {
+                    id: r.int("id", 0)?,
+                    x: r.required("x", Value::as_float)?,
+                    y: r.required("y", Value::as_float)?,
+                    height: r.float("height", 0.0)?,
+                    angle: r.int("angle", 0)?,
+                    kind: r.required("type", Value::as_int)?,
+                    special: r.int("special", 0)?,
+                    args: r.args()?,
+                    extra: r.fields,
+                }),
+                "vertex" => map.vertices.push(UdmfVertex {
+                    x: r.required("x", Value::as_float)?,
+                    y: r.required("y", Value::as_float)?,
+                    extra: r.fields,
+                }),
+                "linedef" => map.linedefs.push(UdmfLinedef {
+                    id: r.int("id", -1)?,
+                    v1: r.required("v1", Value::as_int)?,
+                    v2: r.required("v2", Value::as_int)?,
+                    special: r.int("special", 0)?,
+                    args: r.args()?,
+                    sidefront: r.required("sidefront", Value::as_int)?,
+                    sideback: r.int("sideback", -1)?,
+                    extra: r.fields,
+                }),
+                "sidedef" => map.sidedefs.push(UdmfSidedef {
+                    offsetx: r.int("offsetx", 0)?,
+                    offsety: r.int("offsety", 0)?,
+                    texturetop: r.string("texturetop", "-")?,
+                    texturebottom: r.string("texturebottom", "-")?,
+                    texturemiddle: r.string("texturemiddle", "-")?,
+                    sector: r.required("sector", Value::as_int)?,
+                    extra: r.fields,
+                }),
+                _ => map.sectors.push(UdmfSector {
+                    heightfloor: r.int("heightfloor", 0)?,
+                    heightceiling: r.int("heightceiling", 0)?,
+                    texturefloor: r.required("texturefloor", |v| v.as_str().map(String::from))?,
+                    textureceiling: r
+                        .required("textureceiling", |v| v.as_str().map(String::from))?,
+                    lightlevel: r.int("lightlevel", 160)?,
+                    special: r.int("special", 0)?,
+                    id: r.int("id", 0)?,
+                    extra: r.fields,
+                }),
+            }
+        }
+        Ok(map)
+    }
+
+    /// Reads the TEXTMAP of a UDMF map by marker name.
+    pub fn from_wad(wad: &Wad, name: &str) -> Result<TextMap, MapDecodeError> {
+        let lumps = wad
+            .find_map(name)
+            .ok_or_else(|| MapDecodeError::NoSuchMap(name.into()))?;
+        if lumps.format != MapFormat::Udmf {
+            return Err(MapDecodeError::UnsupportedFormat(lumps.format));
+        }
+        let index = lumps
+            .textmap
+            .ok_or(MapDecodeError::MissingLump("TEXTMAP"))?;
+        let data = wad
+            .read_lump(index)
+            .map_err(MapDecodeError::FailedToReadLump)?;
+        let source =
+            String::from_utf8(data).map_err(|_| MapDecodeError::Udmf(UdmfError::InvalidUtf8))?;
+        TextMap::parse(&source).map_err(MapDecodeError::Udmf)
+    }
+
+    /// The marker, TEXTMAP and ENDMAP lumps of a minimal UDMF map.
+    pub fn to_lumps(&self, marker: LumpName) -> Vec<Lump> {
+        vec![
+            Lump::new(marker, vec![]),
+            Lump::new(LumpName::known("TEXTMAP"), self.to_string().into_bytes()),
+            Lump::new(LumpName::known("ENDMAP"), vec![]),
+        ]
+    }
+}
+impl std::fmt::Display for TextMap {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut out = String::new();
+        {
+            let mut w = FieldWriter { out: &mut out };
+            w.value("namespace", Value::Str(self.namespace.clone()));
+            w.extra(&self.globals);
+        }
+        let mut block = |kind: &str, write: &dyn Fn(&mut FieldWriter)| {
+            let _ = writeln!(out, "\n{}\n{{", kind);
+            write(&mut FieldWriter { out: &mut out });
+            out.push_str("}\n");
+        };
+        for t in &self.things {
+            block("thing", &|w| {
+                w.int("id", t.id, Some(0));
+                w.float("x", t.x, None);
+                w.float("y", t.y, None);
+                w.float("height", t.height, Some(0.0));
+                w.int("angle", t.angle, Some(0));
+                w.int("type", t.kind, None);
+                w.int("special", t.special, Some(0));
+                w.args(&t.args);
+                w.extra(&t.extra);
+            });
+        }
+        for v in &self.vertices {
+            block("vertex", &|w| {
+                w.float("x", v.x, None);
+                w.float("y", v.y, None);
+                w.extra(&v.extra);
+            });
+        }
+        for l in &self.linedefs {
+            block("linedef", &|w| {
+                w.int("id", l.id, Some(-1));
+                w.int("v1", l.v1, None);
+                w.int("v2", l.v2, None);
+                w.int("special", l.special, Some(0));
+                w.args(&l.args);
+                w.int("sidefront", l.sidefront, None);
+                w.int("sideback", l.sideback, Some(-1));
+                w.extra(&l.extra);
+            });
+        }
+        for s in &self.sidedefs {
+            block("sidedef", &|w| {
+                w.int("offsetx", s.offsetx, Some(0));
+                w.int("offsety", s.offsety, Some(0));
+                w.string("texturetop", &s.texturetop, Some("-"));
+                w.string("texturebottom", &s.texturebottom, Some("-"));
+                w.string("texturemiddle", &s.texturemiddle, Some("-"));
+                w.int("sector", s.sector, None);
+                w.extra(&s.extra);
+            });
+        }
+        for s in &self.sectors {
+            block("sector", &|w| {
+                w.int("heightfloor", s.heightfloor, Some(0));
+                w.int("heightceiling", s.heightceiling, Some(0));
+                w.string("texturefloor", &s.texturefloor, None);
+                w.string("textureceiling", &s.textureceiling, None);
+                w.int("lightlevel", s.lightlevel, Some(160));
+                w.int("special", s.special, Some(0));
+                w.int("id", s.id, Some(0));
+                w.extra(&s.extra);
+            });
+        }
+        for b in &self.blocks {
+            block(&b.kind, &|w| w.extra(&b.fields));
+        }
+        f.write_str(&out)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::decode;
+
+    const SOURCE: &str = r#"
+        // A tiny map.
+        namespace = "zdoom";
+        ambientvolume = 0.5;
+        thing { x = 32; y = -16.5; type = 1; angle = 90; skill1 = true; }
+        /* vertices
+           span lines */
+        vertex { x = 0.0; y = 0.0; }
+        vertex { X = 64; y = 0x10; }
+        linedef { v1 = 0; v2 = 1; sidefront = 0; blocking = true; comment = "say \"hi\""; }
+        sidedef { sector = 0; texturemiddle = "STARTAN2"; offsetx = 010; }
+        sector { texturefloor = "FLOOR4_8"; textureceiling = "CEIL3_5"; heightceiling = 128; }
+        custom_block { foo = bar; }
+    "#;
+
+    #[test]
+    fn test_parse() {
+        let map = TextMap::parse(SOURCE).unwrap();
+        assert_eq!(map.namespace, "zdoom");
+        assert_eq!(map.globals, [("ambientvolume".into(), Value::Float(0.5))]);
+        assert_eq!(map.things[0].y, -16.5);
+        assert_eq!(map.things[0].angle, 90);
+        assert!(map.things[0].flag("SKILL1"));
+        assert_eq!(map.vertices[1].x, 64.0);
+        assert_eq!(map.vertices[1].y, 16.0);
+        assert_eq!(map.linedefs[0].sideback, -1);
+        assert!(map.linedefs[0].flag("blocking"));
+        assert_eq!(
+            map.linedefs[0].extra[1],
+            ("comment".into(), Value::Str("say \"hi\"".into()))
+        );
+        assert_eq!(map.sidedefs[0].offsetx, 8);
+        assert_eq!(map.sidedefs[0].texturetop, "-");
+        assert_eq!(map.sectors[0].lightlevel, 160);
+        assert_eq!(map.blocks[0].kind, "custom_block");
+        assert_eq!(
+            map.blocks[0].fields,
+            [("foo".into(), Value::Keyword("bar".into()))]
+        );
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let map = TextMap::parse(SOURCE).unwrap();
+        let text = map.to_string();
+        assert_eq!(TextMap::parse(&text).unwrap(), map);
+    }
+
+    #[test]
+    fn test_errors() {
+        assert!(matches!(
+            TextMap::parse("thing { y = 0; type = 1; }"),
+            Err(UdmfError::MissingField {
+                block: "thing",
+                field: "x"
+            })
+        ));
+        assert!(matches!(
+            TextMap::parse("namespace = \"doom\";\nvertex { x = \"a\"; y = 0; }"),
+            Err(UdmfError::WrongType { line: 2, .. })
+        ));
+        assert!(matches!(
+            TextMap::parse("vertex { x = 1 }"),
+            Err(UdmfError::UnexpectedToken { line: 1, .. })
+        ));
+        assert!(matches!(
+            TextMap::parse("/* open"),
+            Err(UdmfError::UnterminatedComment { line: 1 })
+        ));
+    }
+
+    #[test]
+    fn test_into_wad() {
+        let map = TextMap::parse(SOURCE).unwrap();
+        let mut builder = crate::WadBuilder::pwad();
+        builder
+            .lumps
+            .extend(map.to_lumps(LumpName::from_string("MAP01".into()).unwrap()));
+        let wad = decode(&builder);
+        assert_eq!(TextMap::from_wad(&wad, "MAP01").unwrap(), map);
+    }
+}
@@ -0,0 +1,466 @@
+use crate::{Entry, Lump, LumpName, Wad};
+
+use super::{
+    doom::{encode_records, NO_LINEDEF},
+    extended::gl_group,
+    Blockmap, BlockmapCompression, Linedefs, Map, MapFormat, NodeChild, Reject, Things,
+};
+
+#[derive(Debug)]
+pub enum MapEncodeError {
+    UnsupportedFormat(MapFormat),
+    /// An index that does not fit the vanilla lump layout.
+    IndexOutOfRange {
+        lump: &'static str,
+        index: usize,
+    },
+    /// A GL miniseg, by its index in `segs`.
+    Miniseg(usize),
+    /// Block lists starting past word 32767, which vanilla's signed offsets
+    /// cannot reach.
+    BlockmapTooLarge,
+}
+
+/// Binary map lumps in the order vanilla engines expect after the marker.
+const LUMP_ORDER: [&str; 12] = [
+    "THINGS", "LINEDEFS", "SIDEDEFS", "VERTEXES", "SEGS", "SSECTORS", "NODES", "SECTORS", "REJECT",
+    "BLOCKMAP", "BEHAVIOR", "SCRIPTS",
+];
+
+/// Lumps of a UDMF map, which a binary map replaces.
+const UDMF_LUMPS: [&str; 4] = ["TEXTMAP", "ZNODES", "DIALOGUE", "ENDMAP"];
+
+/// Lumps only Hexen-format maps have.
+const HEXEN_LUMPS: [&str; 2] = ["BEHAVIOR", "SCRIPTS"];
+
+fn check(lump: &'static str, index: u32, max: u32) -> Result<(), MapEncodeError> {
+    if index > max {
+        Err(MapEncodeError::IndexOutOfRange {
+            lump,
+            index: index as usize,
+        })
+    } else {
+        Ok(())
+    }
+}
+
+impl Map {
+    /// Every index the binary lumps store in 16 bits, checked before it is
+    /// truncated.
+    fn check_vanilla_limits(&self) -> Result<(), MapEncodeError> {
+        let linedefs = self.linedefs.len() as u32;
+        for (index, seg) in self.segs.iter().enumerate() {
+            if seg.linedef == NO_LINEDEF {
+                return Err(MapEncodeError::Miniseg(index));
+            }
+            check("SEGS", seg.v1, 0xFFFF)?;
+            check("SEGS", seg.v2, 0xFFFF)?;
+            if seg.linedef as u32 >= linedefs {
+                return Err(MapEncodeError::IndexOutOfRange {
+                    lump: "SEGS",
+                    index: seg.linedef as usize,
+                });
+            }
+        }
+        for subsector in &self.subsectors {
+            check("SSECTORS", subsector.first_seg, 0xFFFF)?;
+            check("SSECTORS", subsector.seg_count, 0xFFFF)?;
+        }
+        for node in &self.nodes {
+            for child in [node.right, node.left] {
+                match child {
+                    NodeChild::Node(index) | NodeChild::SubSector(index) => {
+                        check("NODES", index, 0x7FFF)?
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Encodes the lumps this map owns, in vanilla order and without the
+    /// marker. A REJECT built for a different number of sectors is left out.
+    pub fn component_lumps(&self) -> Result<Vec<Lump>, MapEncodeError> {
+        self.check_vanilla_limits()?;
+        let (things, linedefs) = match (&self.things, &self.linedefs) {
+            (Things::Doom(things), Linedefs::Doom(linedefs)) if self.format == MapFormat::Doom => {
+                (encode_records(things), encode_records(linedefs))
+            }
+            (Things::Hexen(things), Linedefs::Hexen(linedefs))
+                if self.format == MapFormat::Hexen =>
+            {
+                (encode_records(things), encode_records(linedefs))
+            }
+            _ => return Err(MapEncodeError::UnsupportedFormat(self.format)),
+        };
+        let mut lumps = vec![
+            Lump::new(LumpName::known("THINGS"), things),
+            Lump::new(LumpName::known("LINEDEFS"), linedefs),
+            Lump::new(LumpName::known("SIDEDEFS"), encode_records(&self.sidedefs)),
+            Lump::new(LumpName::known("VERTEXES"), encode_records(&self.vertexes)),
+            Lump::new(LumpName::known("SEGS"), encode_records(&self.segs)),
+            Lump::new(
+                LumpName::known("SSECTORS"),
+                encode_records(&self.subsectors),
+            ),
+            Lump::new(LumpName::known("NODES"), encode_records(&self.nodes)),
+            Lump::new(LumpName::known("SECTORS"), encode_records(&self.sectors)),
+        ];
+        if let Some(reject) = self
+            .reject
+            .as_ref()
+            .filter(|reject| reject.sectors == self.sectors.len())
+        {
+            lumps.push(Lump::new(LumpName::known("REJECT"), reject.encode()));
+        }
+        if let Some(blockmap) = &self.blockmap {
+            lumps.push(Lump::new(LumpName::known("BLOCKMAP"), blockmap.encode()?));
+        }
+        if let Some(behavior) = &self.behavior {
+            lumps.push(Lump::new(LumpName::known("BEHAVIOR"), behavior.clone()));
+        }
+        Ok(lumps)
+    }
+
+    /// The marker followed by `component_lumps`.
+    pub fn to_lumps(&self) -> Result<Vec<Lump>, MapEncodeError> {
+        let mut lumps = vec![Lump::new(self.name, vec![])];
+        lumps.extend(self.component_lumps()?);
+        Ok(lumps)
+    }
+
+    /// Replaces this map's lumps in `wad`, or appends the map when the WAD has
+    /// no map of that name. Unknown lumps in the group are kept, and the whole
+    /// group is put back in vanilla order. Lumps of another format are
+    /// dropped: UDMF's TEXTMAP, ZNODES, DIALOGUE and ENDMAP, and Hexen's
+    /// BEHAVIOR and SCRIPTS when writing a Doom map, and the glBSP `GL_`
+    /// group after the map, whose nodes no longer match.
+    ///
+    /// The old REJECT and BLOCKMAP never survive, since they describe the old
+    /// geometry. Vanilla finds both by their position after the marker, so a
+    /// map without them gets an empty REJECT and a freshly built BLOCKMAP.
+    pub fn write_to_wad(&self, wad: &mut Wad) -> Result<(), MapEncodeError> {
+        let mut components = self.component_lumps()?;
+        let has = |lumps: &[Lump], name| lumps.iter().any(|l| l.name == LumpName::known(name));
+        if !has(&components, "REJECT") {
+            let reject = Reject::empty(self.sectors.len());
+            components.push(Lump::new(LumpName::known("REJECT"), reject.encode()));
+        }
+        if !has(&components, "BLOCKMAP") {
+            let blockmap = Blockmap::build(self, BlockmapCompression::default())?;
+            components.push(Lump::new(LumpName::known("BLOCKMAP"), blockmap.encode()?));
+        }
+        let position = |name: &LumpName| {
+            LUMP_ORDER
+                .iter()
+                .position(|known| LumpName::known(known) == *name)
+                .unwrap_or(LUMP_ORDER.len())
+        };
+        components.sort_by_key(|lump| position(&lump.name));
+        let Some(existing) = wad.find_map(&self.name.to_string()) else {
+            wad.push_lump(self.name, vec![]);
+            for lump in components {
+                wad.push_lump(lump.name, lump.data);
+            }
+            return Ok(());
+        };
+        let mut staged: Vec<Entry> = components
+            .into_iter()
+            .map(|lump| Entry::staged(lump.name, lump.data))
+            .collect();
+        wad.directory.drain(gl_group(wad, &existing));
+        let mut kept: Vec<Entry> = wad.directory.drain(existing.range.clone()).collect();
+        let marker = kept.remove(0);
+        let mut group = vec![marker];
+        let is = |entry: &Entry, names: &[&str]| {
+            names.iter().any(|name| LumpName::known(name) == entry.name)
+        };
+        let stale = |entry: &Entry| {
+            staged.iter().any(|s| s.name == entry.name)
+                || is(entry, &UDMF_LUMPS)
+                || (self.format == MapFormat::Doom && is(entry, &HEXEN_LUMPS))
+        };
+        kept.retain(|entry| !stale(entry));
+        staged.extend(kept);
+        staged.sort_by_key(|entry| position(&entry.name));
+        group.extend(staged);
+        let start = existing.range.start;
+        wad.directory.splice(start..start, group);
+        wad.reindex();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::decode;
+    use crate::{
+        map::{Linedef, Sector, Sidedef, Thing, Vertex},
+        WadBuilder,
+    };
+
+    fn square() -> Map {
+        let vertexes = vec![
+            Vertex { x: 0, y: 0 },
+            Vertex { x: 0, y: 64 },
+            Vertex { x: 64, y: 64 },
+            Vertex { x: 64, y: 0 },
+        ];
+        let linedefs = (0..4)
+            .map(|i| Linedef {
+                v1: i,
+                v2: (i + 1) % 4,
+                flags: 1,
+                special: 0,
+                tag: 0,
+                right: Some(i),
+                left: None,
+            })
+            .collect();
+        let sidedef = Sidedef {
+            x_offset: 0,
+            y_offset: 0,
+            upper: LumpName::known("-"),
+            lower: LumpName::known("-"),
+            middle: LumpName::known("STARTAN2"),
+            sector: 0,
+        };
+        Map {
+            name: LumpName::known("MAP01"),
+            format: MapFormat::Doom,
+            things: Things::Doom(vec![Thing {
+                x: 32,
+                y: 32,
+                angle: 90,
+                kind: 1,
+                flags: 7,
+            }]),
+            linedefs: Linedefs::Doom(linedefs),
+            sidedefs: vec![sidedef; 4],
+            vertexes,
+            segs: vec![],
+            subsectors: vec![],
+            nodes: vec![],
+            sectors: vec![Sector {
+                floor_height: 0,
+                ceiling_height: 128,
+                floor_texture: LumpName::known("FLOOR4_8"),
+                ceiling_texture: LumpName::known("CEIL3_5"),
+                light: 160,
+                special: 0,
+                tag: 0,
+            }],
+            reject: None,
+            blockmap: None,
+            behavior: None,
+            warnings: vec![],
+        }
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let map = square();
+        let mut builder = WadBuilder::pwad();
+        builder.lumps.extend(map.to_lumps().unwrap());
+        let wad = decode(&builder);
+        let decoded = Map::from_wad(&wad, "MAP01").unwrap();
+        assert_eq!(decoded.things, map.things);
+        assert_eq!(decoded.linedefs, map.linedefs);
+        assert_eq!(decoded.sidedefs, map.sidedefs);
+        assert_eq!(decoded.vertexes, map.vertexes);
+        assert_eq!(decoded.sectors, map.sectors);
+        assert_eq!(decoded.reject, None);
+    }
+
+    #[test]
+    fn test_writes_generated_blockmap_and_reject() {
+        let mut map = crate::map::testutil::two_rooms();
+        map.build_nodes().unwrap();
+        map.build_blockmap(crate::map::BlockmapCompression::Deduplicate)
+            .unwrap();
+        map.build_reject(crate::map::RejectMode::LineOfSight);
+        let mut builder = WadBuilder::pwad();
+        builder.lumps.extend(map.to_lumps().unwrap());
+        let wad = decode(&builder);
+        let lumps = wad.find_map("MAP01").unwrap();
+        assert_eq!(lumps.reject, Some(9));
+        assert_eq!(lumps.blockmap, Some(10));
+        let decoded = Map::from_wad(&wad, "MAP01").unwrap();
+        assert_eq!(
+            decoded.blockmap.unwrap().blocks,
+            map.blockmap.unwrap().blocks
+        );
+        assert_eq!(decoded.reject, map.reject);
+        assert_eq!(decoded.nodes, map.nodes);
+    }
+
+    #[test]
+    fn test_write_to_wad_replaces_in_place() {
+        let mut wad = decode(
+            &WadBuilder::pwad()
+                .lump(LumpName::known("MAP01"), vec![])
+                .lump(LumpName::known("THINGS"), vec![0; 10])
+                .lump(LumpName::known("LINEDEFS"), vec![])
+                .lump(LumpName::known("SIDEDEFS"), vec![])
+                .lump(LumpName::known("VERTEXES"), vec![])
+                .lump(LumpName::known("SECTORS"), vec![])
+                .lump(LumpName::known("REJECT"), vec![0xAA])
+                .lump(LumpName::known("ENDOOM"), vec![1]),
+        );
+        let mut map = square();
+        map.write_to_wad(&mut wad).unwrap();
+        let names: Vec<String> = wad.directory.iter().map(|e| e.name.to_string()).collect();
+        assert_eq!(
+            names,
+            [
+                "MAP01", "THINGS", "LINEDEFS", "SIDEDEFS", "VERTEXES", "SEGS", "SSECTORS", "NODES",
+                "SECTORS", "REJECT", "BLOCKMAP", "ENDOOM"
+            ]
+        );
+        // The old one-byte REJECT is replaced by one sized for the map.
+        assert_eq!(wad.read_lump(9).unwrap(), [0]);
+        let decoded = Map::from_wad(&wad, "MAP01").unwrap();
+        assert_eq!(decoded.things, map.things);
+        assert_eq!(
+            decoded.blockmap.unwrap().blocks,
+            Blockmap::build(&map, BlockmapCompression::default())
+                .unwrap()
+                .blocks
+        );
+
+        map.name = LumpName::known("MAP02");
+        map.write_to_wad(&mut wad).unwrap();
+        assert_eq!(wad.maps().len(), 2);
+    }
+
+    #[test]
+    fn test_write_to_wad_replaces_udmf_map() {
+        let mut wad = decode(
+            &WadBuilder::pwad()
+                .lump(LumpName::known("MAP01"), vec![])
+                .lump(
+                    LumpName::known("TEXTMAP"),
+                    b"namespace = \"zdoom\";".to_vec(),
+                )
+                .lump(LumpName::known("ZNODES"), vec![1])
+                .lump(LumpName::known("DIALOGUE"), vec![2])
+                .lump(LumpName::known("ENDMAP"), vec![])
+                .lump(LumpName::known("ENDOOM"), vec![3]),
+        );
+        let map = square();
+        map.write_to_wad(&mut wad).unwrap();
+        let names: Vec<String> = wad.directory.iter().map(|e| e.name.to_string()).collect();
+        assert_eq!(
+            names,
+            [
+                "MAP01", "THINGS", "LINEDEFS", "SIDEDEFS", "VERTEXES", "SEGS", "SSECTORS", "NODES",
+                "SECTORS", "REJECT", "BLOCKMAP", "ENDOOM"
+            ]
+        );
+        let decoded = Map::from_wad(&wad, "MAP01").unwrap();
+        assert_eq!(decoded.format, MapFormat::Doom);
+        assert_eq!(decoded.linedefs, map.linedefs);
+    }
+
+    #[test]
+    fn test_vanilla_limits() {
+        let mut map = square();
+        map.nodes.push(crate::map::Node {
+            x: 0,
+            y: 0,
+            dx: 1,
+            dy: 0,
+            right_bbox: crate::map::BoundingBox {
+                top: 0,
+                bottom: 0,
+                left: 0,
+                right: 0,
+            },
+            left_bbox: crate::map::BoundingBox {
+                top: 0,
+                bottom: 0,
+                left: 0,
+                right: 0,
+            },
+            right: NodeChild::SubSector(0x8000),
+            left: NodeChild::SubSector(0),
+        });
+        assert!(matches!(
+            map.to_lumps(),
+            Err(MapEncodeError::IndexOutOfRange {
+                lump: "NODES",
+                index: 0x8000
+            })
+        ));
+
+        let mut map = square();
+        map.build_nodes().unwrap();
+        map.segs[0].v2 = 0x10000;
+        assert!(matches!(
+            map.to_lumps(),
+            Err(MapEncodeError::IndexOutOfRange {
+                lump: "SEGS",
+                index: 0x10000
+            })
+        ));
+
+        let mut map = square();
+        map.build_nodes().unwrap();
+        map.segs[0].linedef = map.linedefs.len() as u16;
+        assert!(matches!(
+            map.to_lumps(),
+            Err(MapEncodeError::IndexOutOfRange { lump: "SEGS", .. })
+        ));
+        map.segs[0].linedef = NO_LINEDEF;
+        assert!(matches!(map.to_lumps(), Err(MapEncodeError::Miniseg(0))));
+
+        let mut map = square();
+        map.build_nodes().unwrap();
+        map.subsectors[0].seg_count = 0x10000;
+        assert!(matches!(
+            map.to_lumps(),
+            Err(MapEncodeError::IndexOutOfRange {
+                lump: "SSECTORS",
+                index: 0x10000
+            })
+        ));
+    }
+
+    #[test]
+    fn test_write_to_wad_drops_mismatched_reject() {
+        let mut wad = decode(&WadBuilder::pwad());
+        let mut map = square();
+        map.reject = Some(Reject::empty(3));
+        map.write_to_wad(&mut wad).unwrap();
+        assert_eq!(
+            Map::from_wad(&wad, "MAP01").unwrap().reject,
+            Some(Reject::empty(1))
+        );
+        assert!(map
+            .component_lumps()
+            .unwrap()
+            .iter()
+            .all(|lump| lump.name != LumpName::known("REJECT")));
+    }
+
+    #[test]
+    fn test_write_to_wad_drops_gl_group() {
+        let mut builder = WadBuilder::pwad();
+        builder.lumps.extend(square().to_lumps().unwrap());
+        for name in [
+            "GL_MAP01", "GL_VERT", "GL_SEGS", "GL_SSECT", "GL_NODES", "GL_PVS",
+        ] {
+            builder.push_lump(LumpName::known(name), vec![1]);
+        }
+        builder.push_lump(LumpName::known("ENDOOM"), vec![2]);
+        let mut wad = decode(&builder);
+        let mut map = square();
+        map.build_nodes().unwrap();
+        map.write_to_wad(&mut wad).unwrap();
+        let names: Vec<String> = wad.directory.iter().map(|e| e.name.to_string()).collect();
+        assert_eq!(names.last().unwrap(), "ENDOOM");
+        assert!(names.iter().all(|name| !name.starts_with("GL_")));
+        assert_eq!(Map::from_wad(&wad, "MAP01").unwrap().nodes, map.nodes);
+    }
+}
@@ -0,0 +1,129 @@
+use crate::{Entry, Wad};
+
+/// Marker-delimited groups of lumps. Lumps outside any marker pair are
+/// `Global`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Namespace {
+    Global,
+    Sprites,
+    Flats,
+    Patches,
+    Colormaps,
+    Textures,
+}
+
+enum Marker {
+    Start(Namespace),
+    End(Namespace),
+    /// Markers such as `F1_START` that subdivide a namespace without
+    /// changing it.
+    Sub,
+}
+
+fn marker(name: &str) -> Option<Marker> {
+    let name = name.to_ascii_uppercase();
+    Some(match name.as_str() {
+        "S_START" | "SS_START" => Marker::Start(Namespace::Sprites),
+        "S_END" | "SS_END" => Marker::End(Namespace::Sprites),
+        "F_START" | "FF_START" => Marker::Start(Namespace::Flats),
+        "F_END" | "FF_END" => Marker::End(Namespace::Flats),
+        "P_START" | "PP_START" => Marker::Start(Namespace::Patches),
+        "P_END" | "PP_END" => Marker::End(Namespace::Patches),
+        "C_START" => Marker::Start(Namespace::Colormaps),
+        "C_END" => Marker::End(Namespace::Colormaps),
+        "TX_START" => Marker::Start(Namespace::Textures),
+        "TX_END" => Marker::End(Namespace::Textures),
+        "F1_START" | "F2_START" | "F3_START" | "F1_END" | "F2_END" | "F3_END" | "P1_START"
+        | "P2_START" | "P3_START" | "P1_END" | "P2_END" | "P3_END" => Marker::Sub,
+        _ => return None,
+    })
+}
+
+/// Assigns a namespace to every entry, `None` for marker lumps.
+///
+/// PWADs are frequently sloppy, so this accepts `FF_END` closing `F_START`
+/// (and the other doubled-letter mixes), ignores stray end markers, and lets
+/// a missing end marker run until the next start marker or the end of the
+/// directory.
+pub(crate) fn build_namespaces(directory: &[Entry]) -> Vec<Option<Namespace>> {
+    let mut current = Namespace::Global;
+    directory
+        .iter()
+        .map(|entry| match marker(&entry.name.to_string()) {
+            Some(Marker::Start(namespace)) => {
+                current = namespace;
+                None
+            }
+            Some(Marker::End(namespace)) => {
+                if current == namespace {
+                    current = Namespace::Global;
+                }
+                None
+            }
+            Some(Marker::Sub) => None,
+            None => Some(current),
+        })
+        .collect()
+}
+
+impl Wad {
+    /// `None` for marker lumps and out-of-range indices.
+    pub fn namespace_of(&self, index: usize) -> Option<Namespace> {
+        self.namespaces.get(index).copied().flatten()
+    }
+
+    pub fn lumps_in(&self, namespace: Namespace) -> impl Iterator<Item = (usize, &Entry)> {
+        self.directory
+            .iter()
+            .enumerate()
+            .filter(move |&(i, _)| self.namespace_of(i) == Some(namespace))
+    }
+
+    /// Last-wins lookup restricted to one namespace.
+    pub fn find_in_namespace_index(&self, name: &str, namespace: Namespace) -> Option<usize> {
+        self.find_all_indices(name)
+            .iter()
+            .rev()
+            .copied()
+            .find(|&i| self.namespace_of(i) == Some(namespace))
+    }
+
+    pub fn find_in_namespace(&self, name: &str, namespace: Namespace) -> Option<&Entry> {
+        self.find_in_namespace_index(name, namespace)
+            .map(|i| &self.directory[i])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::wad_of;
+
+    #[test]
+    fn test_namespaces() {
+        let wad = wad_of(&[
+            "PLAYPAL", "S_START", "TROOA1", "S_END", "F_START", "F1_START", "FLOOR0_1", "F1_END",
+            "NUKAGE1", "FF_END", "P_START", "WALL00", "P_END", "FLOOR0_1",
+        ]);
+        let names =
+            |ns| -> Vec<String> { wad.lumps_in(ns).map(|(_, e)| e.name.to_string()).collect() };
+        assert_eq!(names(Namespace::Sprites), ["TROOA1"]);
+        assert_eq!(names(Namespace::Flats), ["FLOOR0_1", "NUKAGE1"]);
+        assert_eq!(names(Namespace::Patches), ["WALL00"]);
+        assert_eq!(names(Namespace::Global), ["PLAYPAL", "FLOOR0_1"]);
+        assert_eq!(wad.namespace_of(1), None);
+        assert_eq!(
+            wad.find_in_namespace_index("floor0_1", Namespace::Flats),
+            Some(6)
+        );
+        assert_eq!(wad.find_lump_index("FLOOR0_1"), Some(13));
+    }
+
+    #[test]
+    fn test_malformed_markers() {
+        let wad = wad_of(&["FF_START", "FLAT", "S_END", "SS_START", "SPRITE"]);
+        assert_eq!(wad.namespace_of(1), Some(Namespace::Flats));
+        assert_eq!(wad.namespace_of(2), None);
+        assert_eq!(wad.namespace_of(4), Some(Namespace::Sprites));
+    }
+}
@@ -0,0 +1,217 @@
+//! Explicitly little-endian decoding and encoding of the on-disk structures,
+//! independent of host endianness and alignment.
+
+use crate::{Compression, Entry, EntryType, LumpName, Size, WadEncodeError};
+
+pub(crate) fn u8_at(bytes: &[u8], at: usize) -> u8 {
+    bytes[at]
+}
+
+pub(crate) fn i16_at(bytes: &[u8], at: usize) -> i16 {
+    i16::from_le_bytes([bytes[at], bytes[at + 1]])
+}
+
+pub(crate) fn u16_at(bytes: &[u8], at: usize) -> u16 {
+    u16::from_le_bytes([bytes[at], bytes[at + 1]])
+}
+
+pub(crate) fn i32_at(bytes: &[u8], at: usize) -> i32 {
+    i32::from_le_bytes(array_at(bytes, at))
+}
+
+pub(crate) fn array_at<const N: usize>(bytes: &[u8], at: usize) -> [u8; N] {
+    bytes[at..at + N].try_into().unwrap()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct RawHeader {
+    pub signature: [u8; 4],
+    pub count: i32,
+    pub dir_loc: i32,
+}
+impl RawHeader {
+    pub const SIZE: usize = 12;
+
+    pub fn decode(bytes: &[u8]) -> Option<RawHeader> {
+        if bytes.len() < RawHeader::SIZE {
+            return None;
+        }
+        Some(RawHeader {
+            signature: array_at(bytes, 0),
+            count: i32_at(bytes, 4),
+            dir_loc: i32_at(bytes, 8),
+        })
+    }
+
+    pub fn encode(&self, out: &mut Vec<u8>) {
+        out.extend(self.signature);
+        out.extend(self.count.to_le_bytes());
+        out.extend(self.dir_loc.to_le_bytes());
+    }
+}
+
+/// Doom directory entry: 16 bytes, 8-byte name.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct RawEntry {
+    pub file_pos: i32,
+    pub size: i32,
+    pub name: [u8; 8],
+}
+impl RawEntry {
+    pub const SIZE: usize = 16;
+
+    pub fn decode(bytes: &[u8]) -> Option<RawEntry> {
+        if bytes.len() < RawEntry::SIZE {
+            return None;
+        }
+        Some(RawEntry {
+            file_pos: i32_at(bytes, 0),
+            size: i32_at(bytes, 4),
+            name: array_at(bytes, 8),
+        })
+    }
+
+    pub fn encode(&self, out: &mut Vec<u8>) {
+        out.extend(self.file_pos.to_le_bytes());
+        out.extend(self.size.to_le_bytes());
+        out.extend(self.name);
+    }
+}
+/// Fails rather than truncating a name that doesn't fit in 8 bytes.
+impl TryFrom<&Entry> for RawEntry {
+    type Error = WadEncodeError;
+
+    fn try_from(entry: &Entry) -> Result<RawEntry, WadEncodeError> {
+        if entry.name.0[8..].iter().any(|&b| b != 0) {
+            return Err(WadEncodeError::NameTooLong(entry.name));
+        }
+        Ok(RawEntry {
+            file_pos: entry.start.0,
+            size: entry.size.0,
+            name: array_at(&entry.name.0, 0),
+        })
+    }
+}
+impl From<RawEntry> for Entry {
+    fn from(raw: RawEntry) -> Entry {
+        Entry::new(LumpName::from_short_bytes(raw.name), raw.file_pos, raw.size)
+    }
+}
+
+/// WAD2/WAD3 directory entry: 32 bytes, 16-byte name.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct RawLongEntry {
+    pub file_pos: i32,
+    pub disk_size: i32,
+    pub size: i32,
+    pub kind: u8,
+    pub compression: u8,
+    pub padding: i16,
+    pub name: [u8; 16],
+}
+impl RawLongEntry {
+    pub const SIZE: usize = 32;
+
+    pub fn decode(bytes: &[u8]) -> Option<RawLongEntry> {
+        if bytes.len() < RawLongEntry::SIZE {
+            return None;
+        }
+        Some(RawLongEntry {
+            file_pos: i32_at(bytes, 0),
+            disk_size: i32_at(bytes, 4),
+            size: i32_at(bytes, 8),
+            kind: u8_at(bytes, 12),
+            compression: u8_at(bytes, 13),
+            padding: i16_at(bytes, 14),
+            name: array_at(bytes, 16),
+        })
+    }
+
+    pub fn encode(&self, out: &mut Vec<u8>) {
+        out.extend(self.file_pos.to_le_bytes());
+        out.extend(self.disk_size.to_le_bytes());
+        out.extend(self.size.to_le_bytes());
+        out.push(self.kind);
+        out.push(self.compression);
+        out.extend(self.padding.to_le_bytes());
+        out.extend(self.name);
+    }
+}
+impl From<&Entry> for RawLongEntry {
+    fn from(entry: &Entry) -> RawLongEntry {
+        RawLongEntry {
+            file_pos: entry.start.0,
+            disk_size: entry.size.0,
+            size: entry.real_size.0,
+            kind: entry.kind.0,
+            compression: entry.compression.0,
+            padding: entry.padding,
+            name: entry.name.0,
+        }
+    }
+}
+impl From<RawLongEntry> for Entry {
+    fn from(raw: RawLongEntry) -> Entry {
+        let mut entry = Entry::new(LumpName(raw.name), raw.file_pos, raw.disk_size);
+        entry.real_size = Size(raw.size);
+        entry.kind = EntryType(raw.kind);
+        entry.compression = Compression(raw.compression);
+        entry.padding = raw.padding;
+        entry
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_header() {
+        let bytes = [
+            b'I', b'W', b'A', b'D', 0xF0, 0x08, 0, 0, 0x01, 0x02, 0x03, 0x80,
+        ];
+        let header = RawHeader::decode(&bytes).unwrap();
+        assert_eq!(&header.signature, b"IWAD");
+        assert_eq!(header.count, 2288);
+        assert_eq!(header.dir_loc, -0x7FFC_FDFF);
+        let mut out = vec![];
+        header.encode(&mut out);
+        assert_eq!(out, bytes);
+        assert_eq!(RawHeader::decode(&bytes[..11]), None);
+    }
+
+    #[test]
+    fn test_entry() {
+        let bytes = [
+            0x0C, 0, 0, 0, 0x34, 0x12, 0, 0, b'E', b'1', b'M', b'1', 0, 0, 0, 0,
+        ];
+        let raw = RawEntry::decode(&bytes).unwrap();
+        assert_eq!(raw.file_pos, 12);
+        assert_eq!(raw.size, 0x1234);
+        let entry = Entry::from(raw);
+        assert_eq!(entry.name.to_string(), "E1M1");
+        let mut out = vec![];
+        RawEntry::try_from(&entry).unwrap().encode(&mut out);
+        assert_eq!(out, bytes);
+    }
+
+    #[test]
+    fn test_long_entry() {
+        let mut bytes = vec![
+            0x20, 0, 0, 0, 0x10, 0, 0, 0, 0x40, 0, 0, 0, 0x43, 1, 0xFF, 0xFF,
+        ];
+        bytes.extend(b"{window\0garbage!");
+        let raw = RawLongEntry::decode(&bytes).unwrap();
+        let entry = Entry::from(raw);
+        assert_eq!(entry.start.0, 0x20);
+        assert_eq!(entry.size.0, 0x10);
+        assert_eq!(entry.real_size.0, 0x40);
+        assert_eq!(entry.kind.0, 0x43);
+        assert_eq!(entry.compression.0, 1);
+        assert_eq!(entry.padding, -1);
+        assert_eq!(entry.name.to_string(), "{window");
+        let mut out = vec![];
+        RawLongEntry::from(&entry).encode(&mut out);
+        assert_eq!(out, bytes);
+    }
+}
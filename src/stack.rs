@@ -0,0 +1,263 @@
+//! Resource lookup across an IWAD and the PWADs loaded over it.
+
+use std::path::Path;
+
+use crate::gfx::{GraphicDecodeError, IndexedImage, Palette, Picture, TextureDef};
+use crate::map::MapLumps;
+use crate::{Entry, LumpReadError, Namespace, Wad, WadDecodeError};
+
+/// A lump in one of the stack's files.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ResourceRef {
+    /// Position of the file in load order.
+    pub wad: usize,
+    /// Index into that file's directory.
+    pub lump: usize,
+}
+
+/// WADs in load order; later files override earlier ones.
+#[derive(Debug, Default)]
+pub struct WadStack {
+    wads: Vec<Wad>,
+}
+impl WadStack {
+    pub fn new() -> WadStack {
+        WadStack::default()
+    }
+
+    pub fn from_file_paths<P: AsRef<Path>>(
+        paths: impl IntoIterator<Item = P>,
+    ) -> Result<WadStack, WadDecodeError> {
+        let mut stack = WadStack::new();
+        for path in paths {
+            stack.push(Wad::from_file_path(path)?);
+        }
+        Ok(stack)
+    }
+
+    pub fn push(&mut self, wad: Wad) {
+        self.wads.push(wad);
+    }
+
+    pub fn wads(&self) -> &[Wad] {
+        &self.wads
+    }
+
+    pub fn entry(&self, resource: ResourceRef) -> &Entry {
+        &self.wads[resource.wad].directory[resource.lump]
+    }
+
+    pub fn read(&self, resource: ResourceRef) -> Result<Vec<u8>, LumpReadError> {
+        self.wads[resource.wad].read_lump(resource.lump)
+    }
+
+    /// The file the resource came from, for WADs opened from disk.
+    pub fn path_of(&self, resource: ResourceRef) -> Option<&Path> {
+        self.wads[resource.wad].path()
+    }
+
+    fn last(&self, find: impl Fn(&Wad) -> Option<usize>) -> Option<ResourceRef> {
+        self.wads
+            .iter()
+            .enumerate()
+            .rev()
+            .find_map(|(wad, w)| find(w).map(|lump| ResourceRef { wad, lump }))
+    }
+
+    /// Resolves `name` the way engines do for each namespace:
+    ///
+    /// - `Global` takes the last lump with the name in any file, whatever
+    ///   its namespace, like `W_GetNumForName`.
+    /// - Sprites, flats, colormaps and textures only match inside their
+    ///   markers, the last file's copy winning.
+    /// - Patches come from the last file with the name, since many PWADs
+    ///   ship them unmarked; within a file the copy between patch markers
+    ///   wins.
+    pub fn find(&self, name: &str, namespace: Namespace) -> Option<ResourceRef> {
+        match namespace {
+            Namespace::Global => self.last(|wad| wad.find_lump_index(name)),
+            Namespace::Patches => self.last(|wad| {
+                wad.find_in_namespace_index(name, namespace)
+                    .or_else(|| wad.find_lump_index(name))
+            }),
+            _ => self.last(|wad| wad.find_in_namespace_index(name, namespace)),
+        }
+    }
+
+    /// The whole map from the last file that has it; maps never mix lumps
+    /// from several files.
+    pub fn find_map(&self, name: &str) -> Option<(usize, MapLumps)> {
+        self.wads
+            .iter()
+            .enumerate()
+            .rev()
+            .find_map(|(wad, w)| w.find_map(name).map(|lumps| (wad, lumps)))
+    }
+
+    pub fn palette(&self) -> Result<Palette, GraphicDecodeError> {
+        let resource = self
+            .find("PLAYPAL", Namespace::Global)
+            .ok_or_else(|| GraphicDecodeError::NoSuchLump("PLAYPAL".into()))?;
+        let data = self
+            .read(resource)
+            .map_err(GraphicDecodeError::FailedToReadLump)?;
+        Palette::decode(&data).ok_or_else(|| GraphicDecodeError::InvalidLump("PLAYPAL".into()))
+    }
+
+    /// The definition of a composite texture and the file it came from.
+    /// Each file's TEXTURE1/TEXTURE2 is merged over earlier ones by name.
+    pub fn find_texture(
+        &self,
+        name: &str,
+    ) -> Result<Option<(usize, TextureDef)>, GraphicDecodeError> {
+        for (index, wad) in self.wads.iter().enumerate().rev() {
+            if wad.find_lump_index("TEXTURE1").is_none() {
+                continue;
+            }
+            let found = wad
+                .textures()?
+                .into_iter()
+                .find(|texture| texture.name.to_string().eq_ignore_ascii_case(name));
+            if let Some(texture) = found {
+                return Ok(Some((index, texture)));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Renders a composite texture with its file's PNAMES. The patches
+    /// themselves resolve across the whole stack, so a PWAD can replace a
+    /// patch used by an IWAD texture.
+    pub fn compose_texture(&self, name: &str) -> Result<IndexedImage, GraphicDecodeError> {
+        let (wad, texture) = self
+            .find_texture(name)?
+            .ok_or_else(|| GraphicDecodeError::NoSuchLump(name.into()))?;
+        let pnames = self.wads[wad].pnames()?;
+        texture.compose(|index| {
+            let patch = pnames
+                .0
+                .get(index as usize)
+                .ok_or_else(|| GraphicDecodeError::InvalidLump("PNAMES".into()))?
+                .to_string();
+            let resource = self
+                .find(&patch, Namespace::Patches)
+                .ok_or_else(|| GraphicDecodeError::NoSuchLump(patch.clone()))?;
+            let data = self
+                .read(resource)
+                .map_err(GraphicDecodeError::FailedToReadLump)?;
+            Picture::decode(&data).ok_or(GraphicDecodeError::InvalidLump(patch))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gfx::{Pnames, TextureLayout, TextureList, TexturePatch};
+    use crate::testutil::decode;
+    use crate::{LumpName, WadBuilder};
+
+    fn solid(value: u8) -> Vec<u8> {
+        Picture {
+            left: 0,
+            top: 0,
+            image: IndexedImage {
+                width: 2,
+                height: 1,
+                pixels: vec![Some(value); 2],
+            },
+        }
+        .encode()
+        .unwrap()
+    }
+
+    fn stack() -> WadStack {
+        let textures = TextureList {
+            layout: TextureLayout::Doom,
+            textures: vec![TextureDef {
+                name: LumpName::known("WALL"),
+                flags: 0,
+                scale_x: 0,
+                scale_y: 0,
+                width: 2,
+                height: 1,
+                patches: vec![TexturePatch {
+                    x: 0,
+                    y: 0,
+                    patch: 0,
+                }],
+            }],
+        };
+        let iwad = WadBuilder::iwad()
+            .lump(LumpName::known("PLAYPAL"), vec![0; 768])
+            .lump(
+                LumpName::known("PNAMES"),
+                Pnames(vec![LumpName::known("BRICK")]).encode(),
+            )
+            .lump(LumpName::known("TEXTURE1"), textures.encode())
+            .lump(LumpName::known("MAP01"), vec![])
+            .lump(LumpName::known("THINGS"), vec![])
+            .lump(LumpName::known("F_START"), vec![])
+            .lump(LumpName::known("FLAT1"), vec![1; 4096])
+            .lump(LumpName::known("FLAT2"), vec![1; 4096])
+            .lump(LumpName::known("F_END"), vec![])
+            .lump(LumpName::known("P_START"), vec![])
+            .lump(LumpName::known("BRICK"), solid(1))
+            .lump(LumpName::known("P_END"), vec![]);
+        // The PWAD replaces a flat, ships an unmarked patch and a global lump
+        // that happens to share a flat's name.
+        let pwad = WadBuilder::pwad()
+            .lump(LumpName::known("MAP01"), vec![])
+            .lump(LumpName::known("LINEDEFS"), vec![])
+            .lump(LumpName::known("FLAT1"), vec![3])
+            .lump(LumpName::known("BRICK"), solid(2))
+            .lump(LumpName::known("FF_START"), vec![])
+            .lump(LumpName::known("FLAT2"), vec![2; 4096])
+            .lump(LumpName::known("FF_END"), vec![]);
+        let mut stack = WadStack::new();
+        stack.push(decode(&iwad));
+        stack.push(decode(&pwad));
+        stack
+    }
+
+    #[test]
+    fn test_namespace_overrides() {
+        let stack = stack();
+        let at = |wad, lump| Some(ResourceRef { wad, lump });
+        assert_eq!(stack.find("flat2", Namespace::Flats), at(1, 5));
+        assert_eq!(stack.find("FLAT1", Namespace::Flats), at(0, 6));
+        assert_eq!(stack.find("FLAT1", Namespace::Global), at(1, 2));
+        assert_eq!(stack.find("BRICK", Namespace::Patches), at(1, 3));
+        assert_eq!(stack.find("PLAYPAL", Namespace::Sprites), None);
+        assert_eq!(stack.read(at(1, 5).unwrap()).unwrap(), vec![2; 4096]);
+        assert_eq!(stack.path_of(at(1, 5).unwrap()), None);
+        assert_eq!(
+            stack.entry(at(0, 0).unwrap()).name,
+            LumpName::known("PLAYPAL")
+        );
+
+        let (wad, lumps) = stack.find_map("MAP01").unwrap();
+        assert_eq!(wad, 1);
+        assert_eq!(lumps.things, None);
+        assert!(stack.palette().is_ok());
+    }
+
+    #[test]
+    fn test_textures_resolve_patches_across_files() {
+        let mut stack = stack();
+        assert_eq!(stack.find_texture("wall").unwrap().unwrap().0, 0);
+        assert_eq!(stack.compose_texture("WALL").unwrap().pixels, [Some(2); 2]);
+
+        stack.push(decode(
+            &WadBuilder::pwad()
+                .lump(LumpName::known("P_START"), vec![])
+                .lump(LumpName::known("BRICK"), solid(4))
+                .lump(LumpName::known("P_END"), vec![]),
+        ));
+        assert_eq!(stack.compose_texture("WALL").unwrap().pixels, [Some(4); 2]);
+        assert!(matches!(
+            stack.compose_texture("NOSUCH"),
+            Err(GraphicDecodeError::NoSuchLump(_))
+        ));
+    }
+}
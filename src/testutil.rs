@@ -0,0 +1,23 @@
+//! Fixtures shared by the crate's tests.
+
+use crate::{LumpName, Wad, WadBuilder};
+
+pub(crate) fn name(s: &str) -> LumpName {
+    LumpName::from_string(s.into()).unwrap()
+}
+
+/// Writes the builder to memory and decodes the result.
+pub(crate) fn decode(builder: &WadBuilder) -> Wad {
+    let mut bytes = std::io::Cursor::new(vec![]);
+    builder.write_to(&mut bytes).unwrap();
+    Wad::from_bytes(bytes.get_ref()).unwrap()
+}
+
+/// A PWAD of one-byte lumps holding their index.
+pub(crate) fn wad_of(names: &[&str]) -> Wad {
+    let mut builder = WadBuilder::pwad();
+    for (i, lump) in names.iter().enumerate() {
+        builder.push_lump(name(lump), vec![i as u8]);
+    }
+    decode(&builder)
+}
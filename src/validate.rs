@@ -0,0 +1,115 @@
+use crate::{Entry, WadDecodeError};
+
+/// Works out how many directory entries can actually be read. Problems are
+/// pushed to `problems`; the returned count is always safe to loop over.
+pub(crate) fn directory_count(
+    count: i32,
+    dir_loc: i32,
+    entry_size: usize,
+    file_len: u64,
+    problems: &mut Vec<WadDecodeError>,
+) -> u64 {
+    if count < 0 {
+        problems.push(WadDecodeError::NegativeLumpCount(count));
+        return 0;
+    }
+    let fits = if dir_loc < 0 || dir_loc as u64 > file_len {
+        0
+    } else {
+        (file_len - dir_loc as u64) / entry_size as u64
+    };
+    if count as u64 > fits {
+        problems.push(WadDecodeError::DirectoryOutOfBounds {
+            dir_loc,
+            count,
+            file_len,
+        });
+        fits
+    } else {
+        count as u64
+    }
+}
+
+/// Checks every entry against the file length and against each other.
+/// Entries sharing exactly the same data range are not reported, since some
+/// tools deduplicate identical lumps that way.
+pub(crate) fn check_entries(directory: &[Entry], file_len: u64) -> Vec<WadDecodeError> {
+    let mut problems = vec![];
+    let mut in_bounds = vec![];
+    for (index, entry) in directory.iter().enumerate() {
+        let (start, size) = (entry.start.0, entry.size.0);
+        if start < 0 || size < 0 || start as u64 + size as u64 > file_len {
+            problems.push(WadDecodeError::LumpOutOfBounds {
+                index,
+                start,
+                size,
+                file_len,
+            });
+        } else if size > 0 {
+            in_bounds.push((start as u64, size as u64, index));
+        }
+    }
+    in_bounds.sort_unstable();
+    let mut furthest: Option<(u64, u64, usize)> = None;
+    for &(start, size, index) in &in_bounds {
+        if let Some((other_start, other_size, other)) = furthest {
+            let same_range = other_start == start && other_size == size;
+            if start < other_start + other_size && !same_range {
+                problems.push(WadDecodeError::OverlappingLumps { index, other });
+            }
+            if start + size <= other_start + other_size {
+                continue;
+            }
+        }
+        furthest = Some((start, size, index));
+    }
+    problems
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::LumpName;
+
+    fn entry(start: i32, size: i32) -> Entry {
+        Entry::new(LumpName::from_string("X".into()).unwrap(), start, size)
+    }
+
+    #[test]
+    fn test_check_entries() {
+        let directory = [
+            entry(12, 10),
+            entry(12, 10),
+            entry(20, 4),
+            entry(0, 0),
+            entry(90, 20),
+            entry(-1, 2),
+        ];
+        let problems = check_entries(&directory, 100);
+        assert!(matches!(
+            problems[..],
+            [
+                WadDecodeError::LumpOutOfBounds { index: 4, .. },
+                WadDecodeError::LumpOutOfBounds { index: 5, .. },
+                WadDecodeError::OverlappingLumps { index: 2, other: 0 },
+            ]
+        ));
+    }
+
+    #[test]
+    fn test_directory_count() {
+        let mut problems = vec![];
+        assert_eq!(directory_count(3, 12, 16, 60, &mut problems), 3);
+        assert_eq!(directory_count(4, 12, 16, 60, &mut problems), 3);
+        assert_eq!(directory_count(-1, 12, 16, 60, &mut problems), 0);
+        assert_eq!(directory_count(1, 61, 16, 60, &mut problems), 0);
+        assert!(matches!(
+            problems[..],
+            [
+                WadDecodeError::DirectoryOutOfBounds { count: 4, .. },
+                WadDecodeError::NegativeLumpCount(-1),
+                WadDecodeError::DirectoryOutOfBounds { dir_loc: 61, .. },
+            ]
+        ));
+    }
+}